@@ -1,17 +1,139 @@
 use std::{
-    collections::HashMap,
-    io::{Read, stdin},
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    io::{BufRead, Read, Write, stdin, stdout},
+    rc::Rc,
     str::from_utf8,
 };
 
-#[derive(Debug)]
-enum Expression<'a> {
+// A symbol's identity after interning. Cheap to copy and to use as a map
+// key, unlike the raw byte slices symbols used to carry around.
+type SymbolId = u32;
+
+// `_` and `...` are interned first, in that order, by `Interner::new`, so
+// code that needs to recognize them (the macro matcher) can compare against
+// these constants instead of threading the interner through everywhere.
+const UNDERSCORE_ID: SymbolId = 0;
+const ELLIPSIS_ID: SymbolId = 1;
+
+// Maps symbol text to small integer IDs, so the AST and `env` don't need to
+// repeatedly hash/compare byte slices. Each name is copied into an owned
+// `Box<[u8]>` at intern time (rather than borrowed from the source text),
+// so a `Symbol`'s identity never depends on how long the original input
+// slice lives -- `gensym` can mint and intern fresh names with no source
+// text backing them at all. `names` is the reverse mapping, used to render
+// diagnostics, to build `gensym` names, and to recognize special forms and
+// primitives by their text.
+struct Interner {
+    names: Vec<Box<[u8]>>,
+    ids: HashMap<Box<[u8]>, SymbolId>,
+}
+
+impl Interner {
+    fn new() -> Self {
+        let mut interner = Interner {
+            names: Vec::new(),
+            ids: HashMap::new(),
+        };
+        assert_eq!(interner.intern(b"_"), UNDERSCORE_ID);
+        assert_eq!(interner.intern(b"..."), ELLIPSIS_ID);
+        interner
+    }
+
+    fn intern(&mut self, name: &[u8]) -> SymbolId {
+        if let Some(&id) = self.ids.get(name) {
+            return id;
+        }
+        let id = self.names.len() as SymbolId;
+        let name: Box<[u8]> = name.into();
+        self.names.push(name.clone());
+        self.ids.insert(name, id);
+        id
+    }
+
+    fn resolve(&self, id: SymbolId) -> &[u8] {
+        &self.names[id as usize]
+    }
+
+    fn resolve_str(&self, id: SymbolId) -> &str {
+        from_utf8(self.resolve(id)).unwrap()
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Expression {
     Int(u64),
     Bool(bool),
     Char(u8),
-    Symbol(&'a [u8]),
+    Str(Vec<u8>),
+    Symbol(SymbolId),
     Null,
-    Form(Vec<Expression<'a>>),
+    Form(Vec<SExpr>),
+}
+
+// A byte range into the original source, used to render diagnostics.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Span {
+    start: usize,
+    end: usize,
+}
+
+#[derive(Clone, Debug)]
+struct Spanned<T> {
+    value: T,
+    span: Span,
+}
+
+type SExpr = Spanned<Expression>;
+
+#[derive(Debug)]
+struct CompileError {
+    span: Span,
+    message: String,
+}
+
+// Bindings in `env` are either ordinary frame-relative locals, or names bound
+// by a top-level `define` to a compiled function (a `LABEL`/`CALL` pair) plus
+// the free variables it closed over.
+#[derive(Clone, Debug)]
+enum EnvEntry {
+    Local(usize),
+    Function {
+        label: String,
+        arity: usize,
+        captures: Vec<SymbolId>,
+    },
+}
+
+// A persistent, structure-sharing environment: extending a scope allocates
+// one new node and reuses the parent via `Rc`, instead of deep-copying a
+// `HashMap` at every recursive `lower_expression` call.
+enum Env {
+    Empty,
+    Extend(SymbolId, EnvEntry, Rc<Env>),
+}
+
+impl Env {
+    fn empty() -> Rc<Env> {
+        Rc::new(Env::Empty)
+    }
+
+    fn get(&self, name: SymbolId) -> Option<&EnvEntry> {
+        match self {
+            Env::Empty => None,
+            Env::Extend(bound_name, entry, parent) => {
+                if *bound_name == name {
+                    Some(entry)
+                } else {
+                    parent.get(name)
+                }
+            }
+        }
+    }
+
+    fn extend(self: &Rc<Self>, name: SymbolId, entry: EnvEntry) -> Rc<Env> {
+        Rc::new(Env::Extend(name, entry, Rc::clone(self)))
+    }
 }
 
 enum PrimitiveFnArity {
@@ -20,6 +142,268 @@ enum PrimitiveFnArity {
     NaryFold(usize, usize, u64), // implementation_arity, min_args, default_argument
 }
 
+// One of the unary/binary primitive operations, named independently of any
+// particular backend's mnemonic spelling for it.
+#[derive(Clone, Copy)]
+enum Primitive {
+    Add1,
+    Sub1,
+    Add,
+    Sub,
+    Mul,
+    Lt,
+    Eq,
+    EqP,
+    ZeroP,
+    IntegerP,
+    BooleanP,
+    CharP,
+    NullP,
+    Not,
+    CharToInt,
+    IntToChar,
+}
+
+// Lowering (`lower_expression` and friends) walks the AST and decides *what*
+// instructions to emit and in what order; a `Backend` decides how each of
+// those instructions is spelled out as text. This is what lets scrop target
+// more than one textual format from a single AST walk, the way a compiler
+// with several real codegen backends shares one frontend across them.
+trait Backend {
+    fn load_int(&self, x: u64) -> String;
+    fn load_bool(&self, x: bool) -> String;
+    fn load_char(&self, x: u8) -> String;
+    fn load_null(&self) -> String;
+    fn load_unspecified(&self) -> String;
+    fn get(&self, slot: usize) -> String;
+    fn forget(&self) -> String;
+    fn fall(&self) -> String;
+    fn and(&self) -> String;
+    fn primitive(&self, prim: Primitive) -> String;
+    fn label(&self, name: &str) -> String;
+    fn call(&self, label: &str) -> String;
+    fn ret(&self) -> String;
+    fn jump(&self, offset: usize) -> String;
+    fn cjump(&self, offset: usize) -> String;
+
+    // Post-processes the fully lowered instruction stream, once every
+    // instruction's final position is fixed. AsmBackend's JUMP/CJUMP
+    // offsets are already directly interpretable by its own VM, so this is
+    // a no-op; CBackend overrides it to turn its placeholder JUMP/CJUMP
+    // markers into real `goto`s and the labels they target.
+    fn finalize(&self, code: Vec<String>) -> Vec<String> {
+        code
+    }
+}
+
+// The original stack-machine assembly dialect: one mnemonic per line, with
+// JUMP/CJUMP offsets counted in instructions.
+struct AsmBackend;
+
+impl Backend for AsmBackend {
+    fn load_int(&self, x: u64) -> String {
+        format!("LOAD64 {x}")
+    }
+    fn load_bool(&self, x: bool) -> String {
+        format!("LOAD64 {}", if x { "#t" } else { "#f" })
+    }
+    fn load_char(&self, x: u8) -> String {
+        format!("LOAD64 #\\x{x:x}")
+    }
+    fn load_null(&self) -> String {
+        "LOAD64 NULL".to_owned()
+    }
+    fn load_unspecified(&self) -> String {
+        "LOAD64 UNSPECIFIED".to_owned()
+    }
+    fn get(&self, slot: usize) -> String {
+        format!("GET {slot}")
+    }
+    fn forget(&self) -> String {
+        "FORGET".to_owned()
+    }
+    fn fall(&self) -> String {
+        "FALL".to_owned()
+    }
+    fn and(&self) -> String {
+        "AND".to_owned()
+    }
+    fn primitive(&self, prim: Primitive) -> String {
+        match prim {
+            Primitive::Add1 => "ADD1",
+            Primitive::Sub1 => "SUB1",
+            Primitive::Add => "ADD",
+            Primitive::Sub => "SUB",
+            Primitive::Mul => "MUL",
+            Primitive::Lt => "LT",
+            Primitive::Eq => "EQ",
+            Primitive::EqP => "EQP",
+            Primitive::ZeroP => "ZEROP",
+            Primitive::IntegerP => "INTEGERP",
+            Primitive::BooleanP => "BOOLEANP",
+            Primitive::CharP => "CHARP",
+            Primitive::NullP => "NULLP",
+            Primitive::Not => "NOT",
+            Primitive::CharToInt => "CHARTOINT",
+            Primitive::IntToChar => "INTTOCHAR",
+        }
+        .to_owned()
+    }
+    fn label(&self, name: &str) -> String {
+        format!("LABEL {name}")
+    }
+    fn call(&self, label: &str) -> String {
+        format!("CALL {label}")
+    }
+    fn ret(&self) -> String {
+        "RET".to_owned()
+    }
+    fn jump(&self, offset: usize) -> String {
+        format!("JUMP {offset}")
+    }
+    fn cjump(&self, offset: usize) -> String {
+        format!("CJUMP {offset}")
+    }
+}
+
+// A second, C-flavored textual target. Like the assembly backend, this
+// still emits one line per instruction operating on an implicit value
+// stack (`stack`/`sp`) rather than a real recursive-descent C program.
+// JUMP/CJUMP are emitted as placeholder markers (`jump`/`cjump`) and
+// resolved into real `goto` statements and their label targets by
+// `finalize`, once the surrounding instructions' final positions are
+// known -- see `resolve_c_jumps`.
+//
+// `call`/`ret` are NOT lowered to a real calling convention: there is no
+// call-frame or return-address stack, and `get` indexes `stack` by an
+// absolute slot rather than one relative to the current frame. They emit
+// bare `CALL`/`RETURN` macros for the user's own runtime header to define
+// as it sees fit -- a `goto`/`return` definition (as used by this file's
+// own test stub) is enough to make the output compile, but cannot
+// actually call into and return from another function.
+struct CBackend;
+
+impl Backend for CBackend {
+    fn load_int(&self, x: u64) -> String {
+        format!("PUSH({x}L);")
+    }
+    fn load_bool(&self, x: bool) -> String {
+        format!("PUSH({});", if x { 1 } else { 0 })
+    }
+    fn load_char(&self, x: u8) -> String {
+        format!("PUSH({x});")
+    }
+    fn load_null(&self) -> String {
+        "PUSH(SCROP_NULL);".to_owned()
+    }
+    fn load_unspecified(&self) -> String {
+        "PUSH(SCROP_UNSPECIFIED);".to_owned()
+    }
+    fn get(&self, slot: usize) -> String {
+        format!("PUSH(stack[{slot}]);")
+    }
+    fn forget(&self) -> String {
+        "POP();".to_owned()
+    }
+    fn fall(&self) -> String {
+        "FALL();".to_owned()
+    }
+    fn and(&self) -> String {
+        "AND();".to_owned()
+    }
+    fn primitive(&self, prim: Primitive) -> String {
+        let name = match prim {
+            Primitive::Add1 => "scrop_add1",
+            Primitive::Sub1 => "scrop_sub1",
+            Primitive::Add => "scrop_add",
+            Primitive::Sub => "scrop_sub",
+            Primitive::Mul => "scrop_mul",
+            Primitive::Lt => "scrop_lt",
+            Primitive::Eq => "scrop_eq",
+            Primitive::EqP => "scrop_eqp",
+            Primitive::ZeroP => "scrop_zerop",
+            Primitive::IntegerP => "scrop_integerp",
+            Primitive::BooleanP => "scrop_booleanp",
+            Primitive::CharP => "scrop_charp",
+            Primitive::NullP => "scrop_nullp",
+            Primitive::Not => "scrop_not",
+            Primitive::CharToInt => "scrop_char_to_int",
+            Primitive::IntToChar => "scrop_int_to_char",
+        };
+        format!("{name}();")
+    }
+    fn label(&self, name: &str) -> String {
+        format!("{name}:")
+    }
+    // See the `CBackend` doc comment: this is a placeholder left for the
+    // user's runtime to define, not a working call.
+    fn call(&self, label: &str) -> String {
+        format!("CALL({label});")
+    }
+    // See the `CBackend` doc comment: this is a placeholder left for the
+    // user's runtime to define, not a working return.
+    fn ret(&self) -> String {
+        "RETURN();".to_owned()
+    }
+    fn jump(&self, offset: usize) -> String {
+        format!("{C_JUMP_MARKER}{offset}")
+    }
+    fn cjump(&self, offset: usize) -> String {
+        format!("{C_CJUMP_MARKER}{offset}")
+    }
+
+    fn finalize(&self, code: Vec<String>) -> Vec<String> {
+        resolve_c_jumps(code)
+    }
+}
+
+const C_JUMP_MARKER: &str = "\u{0}JUMP ";
+const C_CJUMP_MARKER: &str = "\u{0}CJUMP ";
+
+// Resolves the `JUMP`/`CJUMP` markers `CBackend` emits (instruction-count
+// offsets, the same convention `AsmBackend`'s VM uses) into real `goto`
+// statements and the labels they target -- plain C has no notion of
+// jumping by instruction count. Doing this as a pass over the fully
+// lowered, positionally final instruction stream, rather than threading
+// label names through `lower_expression` itself, is what lets `CBackend`
+// stay a thin per-instruction `Backend` impl like `AsmBackend`: the AST
+// walk stays backend-agnostic, and only `CBackend` needs to know what a
+// `goto` target looks like.
+fn resolve_c_jumps(code: Vec<String>) -> Vec<String> {
+    let mut label_at: HashMap<usize, String> = HashMap::new();
+    let mut gotos: HashMap<usize, String> = HashMap::new();
+    let mut next_label = 0;
+    for (index, line) in code.iter().enumerate() {
+        let (goto, offset) = if let Some(offset) = line.strip_prefix(C_JUMP_MARKER) {
+            ("goto", offset)
+        } else if let Some(offset) = line.strip_prefix(C_CJUMP_MARKER) {
+            ("if (POP()) goto", offset)
+        } else {
+            continue;
+        };
+        let target = index + 1 + offset.parse::<usize>().unwrap();
+        let label = label_at.entry(target).or_insert_with(|| {
+            let label = format!("scrop_l{next_label}");
+            next_label += 1;
+            label
+        });
+        gotos.insert(index, format!("{goto} {label};"));
+    }
+
+    let code_len = code.len();
+    let mut result = Vec::with_capacity(code_len + label_at.len());
+    for (index, line) in code.into_iter().enumerate() {
+        if let Some(label) = label_at.get(&index) {
+            result.push(format!("{label}:;"));
+        }
+        result.push(gotos.remove(&index).unwrap_or(line));
+    }
+    if let Some(label) = label_at.get(&code_len) {
+        result.push(format!("{label}:;"));
+    }
+    result
+}
+
 fn is_delimiter(v: u8) -> bool {
     v.is_ascii_whitespace() || matches!(v, b'(' | b')')
 }
@@ -53,6 +437,7 @@ fn is_symbol_start_char(v: u8) -> bool {
                 | b'/'
                 | b'<'
                 | b'>'
+                | b'.' // needed for the `...` ellipsis marker used by syntax-rules patterns
         )
 }
 
@@ -98,9 +483,51 @@ fn consume_null(input: &[u8]) -> Option<&[u8]> {
     }
 }
 
-fn consume_form<'a>(input: &'a [u8]) -> Option<(Vec<Expression<'a>>, &'a [u8])> {
+// Consumes a `"..."` string literal, decoding `\n`, `\t`, `\r`, `\"`, and
+// `\\` escapes. Any other escaped byte is taken literally (so `\x` just
+// means `x`). Returns `None` if the string is unterminated.
+fn consume_string(input: &[u8]) -> Option<(Vec<u8>, &[u8])> {
+    let mut input = consume_bytes(input, b"\"")?;
+    let mut bytes = Vec::new();
+    loop {
+        match input {
+            [b'"', rest @ ..] => return Some((bytes, rest)),
+            [b'\\', escaped, rest @ ..] => {
+                bytes.push(match escaped {
+                    b'n' => b'\n',
+                    b't' => b'\t',
+                    b'r' => b'\r',
+                    other => *other,
+                });
+                input = rest;
+            }
+            [c, rest @ ..] => {
+                bytes.push(*c);
+                input = rest;
+            }
+            [] => return None,
+        }
+    }
+}
+
+// `'`, `` ` ``, and `,` desugar to `(quote x)`, `(quasiquote x)`, and
+// `(unquote x)` respectively, where `x` is the expression that follows.
+fn consume_quote_marker(input: &[u8]) -> Option<(&'static [u8], &[u8])> {
+    match input.first()? {
+        b'\'' => Some((b"quote", &input[1..])),
+        b'`' => Some((b"quasiquote", &input[1..])),
+        b',' => Some((b"unquote", &input[1..])),
+        _ => None,
+    }
+}
+
+fn consume_form<'a>(
+    origin_len: usize,
+    input: &'a [u8],
+    interner: &mut Interner,
+) -> Option<(Vec<SExpr>, &'a [u8])> {
     if let Some(input) = consume_bytes(input, b"(") {
-        let (args, input) = consume_expressions(consume_whitespace(input));
+        let (args, input) = consume_expressions(origin_len, consume_whitespace(input), interner);
         if let Some(input) = consume_bytes(consume_whitespace(input), b")") {
             Some((args, input))
         } else {
@@ -155,36 +582,108 @@ fn consume_bool(input: &[u8]) -> Option<(bool, &[u8])> {
     }
 }
 
+// Skips ordinary whitespace, `;` line comments, and nestable `#| ... |#`
+// block comments. An unterminated block comment silently consumes the rest
+// of the input; unlike other malformed trailing input, it does not surface
+// as a "leftover data" error one level up.
 fn consume_whitespace(input: &[u8]) -> &[u8] {
-    if input.is_empty() || !input[0].is_ascii_whitespace() {
-        input
-    } else {
+    if !input.is_empty() && input[0].is_ascii_whitespace() {
         consume_whitespace(&input[1..])
+    } else if let Some(input) = consume_bytes(input, b";") {
+        let mut input = input;
+        while !input.is_empty() && input[0] != b'\n' {
+            input = &input[1..];
+        }
+        consume_whitespace(input)
+    } else if let Some(input) = consume_bytes(input, b"#|") {
+        consume_whitespace(consume_block_comment(input, 1))
+    } else {
+        input
+    }
+}
+
+// Consumes the body of a `#| ... |#` block comment (with the opening `#|`
+// already stripped), honoring nested `#| ... |#` comments up to `depth`.
+fn consume_block_comment(input: &[u8], mut depth: usize) -> &[u8] {
+    let mut input = input;
+    while depth > 0 {
+        if let Some(rest) = consume_bytes(input, b"#|") {
+            depth += 1;
+            input = rest;
+        } else if let Some(rest) = consume_bytes(input, b"|#") {
+            depth -= 1;
+            input = rest;
+        } else if input.is_empty() {
+            return input;
+        } else {
+            input = &input[1..];
+        }
     }
+    input
 }
 
-fn consume_expression<'a>(input: &'a [u8]) -> Option<(Expression<'a>, &'a [u8])> {
-    if let Some((v, input)) = consume_int(input) {
-        Some((Expression::Int(v), input))
+// Spans are computed as `origin_len - remaining.len()`, i.e. how much of the
+// original input has been consumed so far; `origin_len` is the length of the
+// whole program, threaded down from `compile_all`. Symbols are interned here,
+// at the parser/AST boundary, so everything downstream of parsing deals only
+// in `SymbolId`s and doesn't need to borrow from the source.
+fn consume_expression<'a>(
+    origin_len: usize,
+    input: &'a [u8],
+    interner: &mut Interner,
+) -> Option<(SExpr, &'a [u8])> {
+    let start = origin_len - input.len();
+    let (value, remaining) = if let Some((v, input)) = consume_int(input) {
+        (Expression::Int(v), input)
     } else if let Some((v, input)) = consume_bool(input) {
-        Some((Expression::Bool(v), input))
+        (Expression::Bool(v), input)
     } else if let Some((v, input)) = consume_character(input) {
-        Some((Expression::Char(v), input))
+        (Expression::Char(v), input)
     } else if let Some(input) = consume_null(input) {
-        Some((Expression::Null, input))
+        (Expression::Null, input)
+    } else if let Some((s, input)) = consume_string(input) {
+        (Expression::Str(s), input)
     } else if let Some((sym, input)) = consume_symbol(input) {
-        Some((Expression::Symbol(sym), input))
-    } else if let Some((args, input)) = consume_form(input) {
-        Some((Expression::Form(args), input))
+        (Expression::Symbol(interner.intern(sym)), input)
+    } else if let Some((args, input)) = consume_form(origin_len, input, interner) {
+        (Expression::Form(args), input)
+    } else if let Some((marker, input)) = consume_quote_marker(input) {
+        let (inner, input) = consume_expression(origin_len, consume_whitespace(input), interner)?;
+        let marker_id = interner.intern(marker);
+        (
+            Expression::Form(vec![
+                Spanned {
+                    value: Expression::Symbol(marker_id),
+                    span: Span {
+                        start,
+                        end: start + 1,
+                    },
+                },
+                inner,
+            ]),
+            input,
+        )
     } else {
-        None
-    }
+        return None;
+    };
+    let end = origin_len - remaining.len();
+    Some((
+        Spanned {
+            value,
+            span: Span { start, end },
+        },
+        remaining,
+    ))
 }
 
-fn consume_expressions<'a>(mut input: &'a [u8]) -> (Vec<Expression<'a>>, &'a [u8]) {
+fn consume_expressions<'a>(
+    origin_len: usize,
+    mut input: &'a [u8],
+    interner: &mut Interner,
+) -> (Vec<SExpr>, &'a [u8]) {
     let mut result = Vec::new();
     while !input.is_empty()
-        && let Some((exp, new_input)) = consume_expression(input)
+        && let Some((exp, new_input)) = consume_expression(origin_len, input, interner)
     {
         result.push(exp);
         input = consume_whitespace(new_input);
@@ -192,132 +691,730 @@ fn consume_expressions<'a>(mut input: &'a [u8]) -> (Vec<Expression<'a>>, &'a [u8
     (result, input)
 }
 
-fn lower_expression<'a>(
-    exp: Expression<'a>,
-    env: HashMap<&'a [u8], usize>,
+// A single `(pattern template)` clause of a `syntax-rules` macro. `pattern`'s
+// first element is the macro-keyword placeholder (conventionally `_`) and is
+// never matched against anything; only `pattern[1..]` is matched against a
+// use-site form's arguments.
+#[derive(Clone, Debug)]
+struct SyntaxRule {
+    pattern: Vec<SExpr>,
+    template: SExpr,
+}
+
+#[derive(Clone, Debug)]
+struct Macro {
+    literals: HashSet<SymbolId>,
+    rules: Vec<SyntaxRule>,
+}
+
+// What a pattern variable captured: either a single subform, or -- for a
+// variable under a `pat ...`  -- an ordered sequence of per-repetition
+// bindings (themselves possibly `Many`, for nested ellipses).
+#[derive(Clone, Debug)]
+enum Binding {
+    One(SExpr),
+    Many(Vec<Binding>),
+}
+
+// Fresh, never-before-seen symbol for macro hygiene. `Interner::intern`
+// copies the name into its own storage, so there's no original source text
+// for this symbol to borrow from.
+fn gensym(interner: &mut Interner, base: SymbolId, counter: &mut usize) -> SymbolId {
+    *counter += 1;
+    let name = format!("{}%{}", interner.resolve_str(base), counter);
+    interner.intern(name.as_bytes())
+}
+
+// Collects the names a pattern (or, with `literals` empty, a template
+// subform) refers to via plain symbols, skipping `_` and the `...` marker.
+fn pattern_vars(pattern: &SExpr, literals: &HashSet<SymbolId>, vars: &mut HashSet<SymbolId>) {
+    match &pattern.value {
+        Expression::Symbol(UNDERSCORE_ID) | Expression::Symbol(ELLIPSIS_ID) => {}
+        Expression::Symbol(name) if !literals.contains(name) => {
+            vars.insert(*name);
+        }
+        Expression::Form(elems) => {
+            for e in elems {
+                pattern_vars(e, literals, vars);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn match_one(
+    pattern: &SExpr,
+    input: &SExpr,
+    literals: &HashSet<SymbolId>,
+    bindings: &mut HashMap<SymbolId, Binding>,
+) -> bool {
+    match &pattern.value {
+        Expression::Symbol(UNDERSCORE_ID) => true,
+        Expression::Symbol(name) if literals.contains(name) => {
+            matches!(&input.value, Expression::Symbol(n) if n == name)
+        }
+        Expression::Symbol(name) => {
+            bindings.insert(*name, Binding::One(input.clone()));
+            true
+        }
+        Expression::Form(sub_pattern) => {
+            if let Expression::Form(sub_input) = &input.value {
+                match_pattern(sub_pattern, sub_input, literals, bindings)
+            } else {
+                false
+            }
+        }
+        Expression::Int(x) => matches!(&input.value, Expression::Int(y) if x == y),
+        Expression::Bool(x) => matches!(&input.value, Expression::Bool(y) if x == y),
+        Expression::Char(x) => matches!(&input.value, Expression::Char(y) if x == y),
+        Expression::Str(s) => matches!(&input.value, Expression::Str(t) if s == t),
+        Expression::Null => matches!(&input.value, Expression::Null),
+    }
+}
+
+// Matches a (sub)pattern's elements against a form's elements, honoring a
+// single `elem ...` per pattern (the subset of `syntax-rules` scrop
+// supports): `elem` may repeat zero or more times, with each pattern
+// variable under it capturing a `Binding::Many` of its per-repetition value.
+fn match_pattern(
+    pattern: &[SExpr],
+    input: &[SExpr],
+    literals: &HashSet<SymbolId>,
+    bindings: &mut HashMap<SymbolId, Binding>,
+) -> bool {
+    let mut pi = 0;
+    let mut ii = 0;
+    while pi < pattern.len() {
+        let is_ellipsis_next = matches!(
+            pattern.get(pi + 1).map(|e| &e.value),
+            Some(Expression::Symbol(ELLIPSIS_ID))
+        );
+        if is_ellipsis_next {
+            let sub_pattern = &pattern[pi];
+            let trailing_fixed = pattern.len() - (pi + 2);
+            let available = input.len().saturating_sub(ii);
+            let repeat_count = available.saturating_sub(trailing_fixed);
+
+            let mut vars = HashSet::new();
+            pattern_vars(sub_pattern, literals, &mut vars);
+            let mut collected: HashMap<SymbolId, Vec<Binding>> =
+                vars.iter().map(|v| (*v, Vec::new())).collect();
+
+            for _ in 0..repeat_count {
+                let mut sub_bindings = HashMap::new();
+                if ii >= input.len() || !match_one(sub_pattern, &input[ii], literals, &mut sub_bindings) {
+                    return false;
+                }
+                for var in &vars {
+                    if let Some(b) = sub_bindings.remove(var) {
+                        collected.get_mut(var).unwrap().push(b);
+                    }
+                }
+                ii += 1;
+            }
+            for (var, seq) in collected {
+                bindings.insert(var, Binding::Many(seq));
+            }
+            pi += 2;
+        } else {
+            if ii >= input.len() || !match_one(&pattern[pi], &input[ii], literals, bindings) {
+                return false;
+            }
+            pi += 1;
+            ii += 1;
+        }
+    }
+    ii == input.len()
+}
+
+// Scans a macro's template for `let`/`lambda` binder symbols that are *not*
+// pattern variables, so they can be gensym-renamed before substitution. This
+// keeps template-introduced bindings (e.g. the `t` in a typical hygienic
+// `or` macro) from capturing identifiers the macro user passed in.
+fn collect_template_binders(
+    template: &SExpr,
+    bindings: &HashMap<SymbolId, Binding>,
+    let_id: SymbolId,
+    lambda_id: SymbolId,
+    binders: &mut HashSet<SymbolId>,
+) {
+    if let Expression::Form(elems) = &template.value {
+        match elems.split_first() {
+            Some((head, rest)) if matches!(&head.value, Expression::Symbol(name) if *name == let_id) => {
+                if let Some(Spanned {
+                    value: Expression::Form(bs),
+                    ..
+                }) = rest.first()
+                {
+                    for b in bs {
+                        if let Expression::Form(b) = &b.value
+                            && let Some(Spanned {
+                                value: Expression::Symbol(name),
+                                ..
+                            }) = b.first()
+                            && !bindings.contains_key(name)
+                        {
+                            binders.insert(*name);
+                        }
+                    }
+                }
+            }
+            Some((head, rest)) if matches!(&head.value, Expression::Symbol(name) if *name == lambda_id) => {
+                if let Some(Spanned {
+                    value: Expression::Form(params),
+                    ..
+                }) = rest.first()
+                {
+                    for p in params {
+                        if let Expression::Symbol(name) = p.value
+                            && !bindings.contains_key(&name)
+                        {
+                            binders.insert(name);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+        for e in elems {
+            collect_template_binders(e, bindings, let_id, lambda_id, binders);
+        }
+    }
+}
+
+fn substitute(
+    template: &SExpr,
+    bindings: &HashMap<SymbolId, Binding>,
+    renames: &HashMap<SymbolId, SymbolId>,
+) -> SExpr {
+    match &template.value {
+        Expression::Symbol(name) => match bindings.get(name) {
+            Some(Binding::One(e)) => e.clone(),
+            Some(Binding::Many(_)) => template.clone(), // misuse (var without `...`); left as-is
+            None => match renames.get(name) {
+                Some(renamed) => Spanned {
+                    value: Expression::Symbol(*renamed),
+                    span: template.span,
+                },
+                None => template.clone(),
+            },
+        },
+        Expression::Form(elems) => {
+            let mut result = Vec::new();
+            let mut i = 0;
+            while i < elems.len() {
+                let has_ellipsis = matches!(
+                    elems.get(i + 1).map(|e| &e.value),
+                    Some(Expression::Symbol(ELLIPSIS_ID))
+                );
+                if has_ellipsis {
+                    let sub = &elems[i];
+                    let mut vars = HashSet::new();
+                    pattern_vars(sub, &HashSet::new(), &mut vars);
+                    let count = vars
+                        .iter()
+                        .find_map(|v| match bindings.get(v) {
+                            Some(Binding::Many(seq)) => Some(seq.len()),
+                            _ => None,
+                        })
+                        .unwrap_or(0);
+                    for k in 0..count {
+                        let mut sub_bindings = bindings.clone();
+                        for v in &vars {
+                            if let Some(Binding::Many(seq)) = bindings.get(v) {
+                                sub_bindings.insert(*v, seq[k].clone());
+                            }
+                        }
+                        result.push(substitute(sub, &sub_bindings, renames));
+                    }
+                    i += 2;
+                } else {
+                    result.push(substitute(&elems[i], bindings, renames));
+                    i += 1;
+                }
+            }
+            Spanned {
+                value: Expression::Form(result),
+                span: template.span,
+            }
+        }
+        _ => template.clone(),
+    }
+}
+
+// Parses a top-level `(define-syntax name (syntax-rules (literals...)
+// (pattern template)...))` and registers it in `macros`.
+fn register_macro(
+    exp: SExpr,
+    macros: &mut HashMap<SymbolId, Macro>,
+    interner: &Interner,
+) -> Result<(), CompileError> {
+    let exp_span = exp.span;
+    let mut form = match exp.value {
+        Expression::Form(form) => form,
+        _ => unreachable!("caller only passes define-syntax forms"),
+    };
+    form.remove(0); // `define-syntax`
+    if form.len() != 2 {
+        return Err(CompileError {
+            span: exp_span,
+            message: "define-syntax requires exactly a name and a syntax-rules form".to_owned(),
+        });
+    }
+    let name_exp = form.remove(0);
+    let name = match name_exp.value {
+        Expression::Symbol(name) => name,
+        _ => {
+            return Err(CompileError {
+                span: name_exp.span,
+                message: "define-syntax name must be a symbol".to_owned(),
+            });
+        }
+    };
+
+    let rules_exp = form.remove(0);
+    let rules_span = rules_exp.span;
+    let mut rules_form = match rules_exp.value {
+        Expression::Form(form)
+            if matches!(
+                form.first().map(|e| &e.value),
+                Some(Expression::Symbol(name)) if interner.resolve(*name) == b"syntax-rules"
+            ) =>
+        {
+            form
+        }
+        _ => {
+            return Err(CompileError {
+                span: rules_span,
+                message: "define-syntax value must be a syntax-rules form".to_owned(),
+            });
+        }
+    };
+    rules_form.remove(0); // `syntax-rules`
+    if rules_form.is_empty() {
+        return Err(CompileError {
+            span: rules_span,
+            message: "syntax-rules requires a literals list".to_owned(),
+        });
+    }
+
+    let literals_exp = rules_form.remove(0);
+    let literals_span = literals_exp.span;
+    let literals: HashSet<SymbolId> = match literals_exp.value {
+        Expression::Form(lits) => {
+            let mut set = HashSet::new();
+            for lit in lits {
+                match lit.value {
+                    Expression::Symbol(name) => {
+                        set.insert(name);
+                    }
+                    _ => {
+                        return Err(CompileError {
+                            span: lit.span,
+                            message: "syntax-rules literals must be symbols".to_owned(),
+                        });
+                    }
+                }
+            }
+            set
+        }
+        _ => {
+            return Err(CompileError {
+                span: literals_span,
+                message: "syntax-rules literals must be a form".to_owned(),
+            });
+        }
+    };
+
+    let mut rules = Vec::new();
+    for rule_exp in rules_form {
+        let rule_span = rule_exp.span;
+        let mut rule_form = match rule_exp.value {
+            Expression::Form(form) => form,
+            _ => {
+                return Err(CompileError {
+                    span: rule_span,
+                    message: "syntax-rules rule must be a form".to_owned(),
+                });
+            }
+        };
+        if rule_form.len() != 2 {
+            return Err(CompileError {
+                span: rule_span,
+                message: "syntax-rules rule must have exactly a pattern and a template".to_owned(),
+            });
+        }
+        let template = rule_form.remove(1);
+        let pattern_exp = rule_form.remove(0);
+        let pattern = match pattern_exp.value {
+            Expression::Form(pattern) => pattern,
+            _ => {
+                return Err(CompileError {
+                    span: pattern_exp.span,
+                    message: "syntax-rules pattern must be a form".to_owned(),
+                });
+            }
+        };
+        rules.push(SyntaxRule { pattern, template });
+    }
+
+    if macros.insert(name, Macro { literals, rules }).is_some() {
+        return Err(CompileError {
+            span: name_exp.span,
+            message: format!(
+                "Duplicate macro definition for '{}'",
+                interner.resolve_str(name)
+            ),
+        });
+    }
+    Ok(())
+}
+
+// Expands macro uses in `exp`, recursing into the result so that a template
+// that itself produces a macro use (the common recursive-macro pattern) gets
+// expanded too.
+fn expand_macros(
+    exp: SExpr,
+    macros: &HashMap<SymbolId, Macro>,
+    interner: &mut Interner,
+    counter: &mut usize,
+) -> Result<SExpr, CompileError> {
+    if let Expression::Form(args) = &exp.value
+        && let Some(Spanned {
+            value: Expression::Symbol(name),
+            ..
+        }) = args.first()
+        && let Some(mac) = macros.get(name)
+    {
+        for rule in &mac.rules {
+            let mut bindings = HashMap::new();
+            if !rule.pattern.is_empty()
+                && match_pattern(&rule.pattern[1..], &args[1..], &mac.literals, &mut bindings)
+            {
+                let mut binders = HashSet::new();
+                collect_template_binders(
+                    &rule.template,
+                    &bindings,
+                    interner.intern(b"let"),
+                    interner.intern(b"lambda"),
+                    &mut binders,
+                );
+                let renames: HashMap<SymbolId, SymbolId> = binders
+                    .into_iter()
+                    .map(|name| (name, gensym(interner, name, counter)))
+                    .collect();
+                let expanded = substitute(&rule.template, &bindings, &renames);
+                return expand_macros(expanded, macros, interner, counter);
+            }
+        }
+        return Err(CompileError {
+            span: exp.span,
+            message: format!(
+                "No matching syntax-rules pattern for macro '{}'",
+                interner.resolve_str(*name)
+            ),
+        });
+    }
+
+    match exp.value {
+        Expression::Form(args) => {
+            let mut new_args = Vec::with_capacity(args.len());
+            for arg in args {
+                new_args.push(expand_macros(arg, macros, interner, counter)?);
+            }
+            Ok(Spanned {
+                value: Expression::Form(new_args),
+                span: exp.span,
+            })
+        }
+        other => Ok(Spanned {
+            value: other,
+            span: exp.span,
+        }),
+    }
+}
+
+fn lower_expression(
+    exp: SExpr,
+    env: Rc<Env>,
     stack_slots_used: usize,
-) -> Vec<String> {
+    functions: &mut Vec<String>,
+    interner: &Interner,
+    backend: &dyn Backend,
+) -> Result<Vec<String>, CompileError> {
+    let exp_span = exp.span;
     let mut result = Vec::new();
-    match exp {
-        Expression::Int(x) => result.push("LOAD64 ".to_owned() + &x.to_string()),
-        Expression::Char(x) => result.push("LOAD64 #\\".to_owned() + format!("x{:x}", x).as_str()),
-        Expression::Bool(x) => result.push("LOAD64 ".to_owned() + if x { "#t" } else { "#f" }),
+    match exp.value {
+        Expression::Int(x) => result.push(backend.load_int(x)),
+        Expression::Char(x) => result.push(backend.load_char(x)),
+        Expression::Bool(x) => result.push(backend.load_bool(x)),
         Expression::Form(mut args) => {
             if args.is_empty() {
-                panic!("Empty form!");
+                return Err(CompileError {
+                    span: exp_span,
+                    message: "Empty form!".to_owned(),
+                });
             }
-            if let Expression::Symbol(name) = args.remove(0) {
-                if env.contains_key(name) {
-                    todo!("Function calls are not yet implemented.")
+            let head = args.remove(0);
+            let head_span = head.span;
+            if let Expression::Symbol(name) = head.value {
+                if let Some(entry) = env.get(name) {
+                    match entry.clone() {
+                        EnvEntry::Function {
+                            label,
+                            arity,
+                            captures,
+                        } => {
+                            if args.len() != arity {
+                                return Err(CompileError {
+                                    span: exp_span,
+                                    message: format!(
+                                        "Incorrect argument count calling '{}'",
+                                        interner.resolve_str(name)
+                                    ),
+                                });
+                            }
+                            for (stack_slots_used, arg) in (stack_slots_used..).zip(args) {
+                                result.append(&mut lower_expression(
+                                    arg,
+                                    Rc::clone(&env),
+                                    stack_slots_used,
+                                    functions,
+                                    interner,
+                                    backend,
+                                )?);
+                            }
+                            for capture in &captures {
+                                match env.get(*capture) {
+                                    Some(EnvEntry::Local(idx)) => result.push(backend.get(*idx)),
+                                    _ => {
+                                        return Err(CompileError {
+                                            span: head_span,
+                                            message: format!(
+                                                "Captured variable '{}' is not available at this call site",
+                                                interner.resolve_str(*capture)
+                                            ),
+                                        });
+                                    }
+                                }
+                            }
+                            result.push(backend.call(&label));
+                            for _ in 0..(arity + captures.len()) {
+                                result.push(backend.fall());
+                            }
+                        }
+                        EnvEntry::Local(_) => {
+                            return Err(CompileError {
+                                span: head_span,
+                                message: format!(
+                                    "Cannot call '{}' because it is not a function",
+                                    interner.resolve_str(name)
+                                ),
+                            });
+                        }
+                    }
+                    return Ok(result);
                 }
-                match name {
+                match interner.resolve(name) {
                     b"let" => {
-                        if let Expression::Form(bindings) = args.remove(0) {
-                            let mut new_env = env.clone();
+                        let bindings_exp = args.remove(0);
+                        if let Expression::Form(bindings) = bindings_exp.value {
+                            let mut new_env = Rc::clone(&env);
                             let mut stack_slots_used = stack_slots_used;
                             let num_bindings = bindings.len();
 
                             for binding in bindings {
-                                if let Expression::Form(mut binding) = binding {
+                                let binding_span = binding.span;
+                                if let Expression::Form(mut binding) = binding.value {
                                     if binding.len() != 2 {
-                                        panic!("let binding has incorrect argument count.")
+                                        return Err(CompileError {
+                                            span: binding_span,
+                                            message: "let binding has incorrect argument count."
+                                                .to_owned(),
+                                        });
                                     }
-                                    if let (Expression::Symbol(name), exp) =
-                                        (binding.remove(0), binding.remove(0))
-                                    {
-                                        if new_env.insert(name, stack_slots_used).is_some() {
-                                            panic!("Duplicate key in let binding");
+                                    let (name_exp, exp) = (binding.remove(0), binding.remove(0));
+                                    let name_span = name_exp.span;
+                                    if let Expression::Symbol(name) = name_exp.value {
+                                        if new_env.get(name).is_some() {
+                                            return Err(CompileError {
+                                                span: name_span,
+                                                message: "Duplicate key in let binding".to_owned(),
+                                            });
                                         }
                                         result.append(&mut lower_expression(
                                             exp,
-                                            env.clone(),
+                                            Rc::clone(&env),
                                             stack_slots_used,
-                                        ));
+                                            functions,
+                                            interner,
+                                            backend,
+                                        )?);
+                                        new_env =
+                                            new_env.extend(name, EnvEntry::Local(stack_slots_used));
                                         stack_slots_used += 1;
                                     } else {
-                                        panic!("let binding args are not (Symbol, Expr)")
+                                        return Err(CompileError {
+                                            span: name_span,
+                                            message: "let binding args are not (Symbol, Expr)"
+                                                .to_owned(),
+                                        });
                                     }
                                 } else {
-                                    panic!("let binding is not a form")
+                                    return Err(CompileError {
+                                        span: binding_span,
+                                        message: "let binding is not a form".to_owned(),
+                                    });
                                 }
                             }
 
-                            result.append(&mut lower_expressions(args, new_env, stack_slots_used));
+                            result.append(&mut lower_expressions(
+                                args,
+                                new_env,
+                                stack_slots_used,
+                                functions,
+                                interner,
+                                backend,
+                            )?);
                             for _ in 0..num_bindings {
-                                result.push("FALL".to_owned());
+                                result.push(backend.fall());
                             }
                         } else {
-                            panic!("let bindings is not a form")
+                            return Err(CompileError {
+                                span: bindings_exp.span,
+                                message: "let bindings is not a form".to_owned(),
+                            });
                         }
                     }
                     b"if" => {
                         let mut stack_slots_used = stack_slots_used;
                         if !matches!(args.len(), 2 | 3) {
-                            panic!("Invalid argument count to if")
+                            return Err(CompileError {
+                                span: exp_span,
+                                message: "Invalid argument count to if".to_owned(),
+                            });
                         }
                         // cond
                         result.append(&mut lower_expression(
                             args.remove(0),
-                            env.clone(),
+                            Rc::clone(&env),
                             stack_slots_used,
-                        ));
+                            functions,
+                            interner,
+                            backend,
+                        )?);
                         stack_slots_used += 1; // cond
-                        result.push("LOAD64 #f".to_owned());
+                        result.push(backend.load_bool(false));
                         stack_slots_used += 1; // load
-                        result.push("EQP".to_owned());
+                        result.push(backend.primitive(Primitive::EqP));
                         stack_slots_used -= 1; // eqp
 
                         // consequent
-                        let mut consequent_code =
-                            lower_expression(args.remove(0), env.clone(), stack_slots_used);
+                        let mut consequent_code = lower_expression(
+                            args.remove(0),
+                            Rc::clone(&env),
+                            stack_slots_used,
+                            functions,
+                            interner,
+                            backend,
+                        )?;
 
                         // alternative
                         let mut alternative_code = if let Some(alternative_code) = args.pop() {
-                            lower_expression(alternative_code, env.clone(), stack_slots_used)
+                            lower_expression(
+                                alternative_code,
+                                Rc::clone(&env),
+                                stack_slots_used,
+                                functions,
+                                interner,
+                                backend,
+                            )?
                         } else {
-                            vec!["LOAD64 UNSPECIFIED".to_owned()]
+                            vec![backend.load_unspecified()]
                         };
 
-                        consequent_code
-                            .push("JUMP ".to_owned() + &alternative_code.len().to_string());
+                        consequent_code.push(backend.jump(alternative_code.len()));
 
-                        result.push("CJUMP ".to_owned() + &consequent_code.len().to_string());
+                        result.push(backend.cjump(consequent_code.len()));
                         result.append(&mut consequent_code);
                         result.append(&mut alternative_code);
                     }
-                    _ => {
-                        let (arity, mnemonic) = match name {
-                            b"add1" => (PrimitiveFnArity::Unary, "ADD1"),
-                            b"sub1" => (PrimitiveFnArity::Unary, "SUB1"),
-                            b"+" => (PrimitiveFnArity::NaryFold(2, 0, 0), "ADD"),
-                            b"-" => (PrimitiveFnArity::NaryFold(2, 1, 0), "SUB"),
-                            b"*" => (PrimitiveFnArity::NaryFold(2, 0, 1), "MUL"),
-                            b"<" => (PrimitiveFnArity::NaryAllPairs(2), "LT"),
-                            b"=" => (PrimitiveFnArity::NaryAllPairs(2), "EQ"),
-                            b"eq?" => (PrimitiveFnArity::NaryAllPairs(2), "EQP"),
-                            b"zero?" => (PrimitiveFnArity::Unary, "ZEROP"),
-                            b"integer?" => (PrimitiveFnArity::Unary, "INTEGERP"),
-                            b"boolean?" => (PrimitiveFnArity::Unary, "BOOLEANP"),
-                            b"char?" => (PrimitiveFnArity::Unary, "CHARP"),
-                            b"null?" => (PrimitiveFnArity::Unary, "NULLP"),
-                            b"not" => (PrimitiveFnArity::Unary, "NOT"),
-                            b"char->integer" => (PrimitiveFnArity::Unary, "CHARTOINT"),
-                            b"integer->char" => (PrimitiveFnArity::Unary, "INTTOCHAR"),
-                            _ => panic!("Cannot resolve symbol '{name:?}'"),
+                    b"lambda" => {
+                        return Err(CompileError {
+                            span: exp_span,
+                            message: "lambda is only supported as the value of a top-level define"
+                                .to_owned(),
+                        });
+                    }
+                    b"quote" => {
+                        if args.len() != 1 {
+                            return Err(CompileError {
+                                span: exp_span,
+                                message: "quote requires exactly one argument".to_owned(),
+                            });
+                        }
+                        let quoted = args.remove(0);
+                        match quoted.value {
+                            Expression::Int(x) => result.push(backend.load_int(x)),
+                            Expression::Bool(x) => result.push(backend.load_bool(x)),
+                            Expression::Char(x) => result.push(backend.load_char(x)),
+                            Expression::Null => result.push(backend.load_null()),
+                            _ => {
+                                return Err(CompileError {
+                                    span: quoted.span,
+                                    message: "quote only supports literal data (integers, booleans, characters, and '())".to_owned(),
+                                });
+                            }
+                        }
+                    }
+                    resolved_name => {
+                        let (arity, prim) = match resolved_name {
+                            b"add1" => (PrimitiveFnArity::Unary, Primitive::Add1),
+                            b"sub1" => (PrimitiveFnArity::Unary, Primitive::Sub1),
+                            b"+" => (PrimitiveFnArity::NaryFold(2, 0, 0), Primitive::Add),
+                            b"-" => (PrimitiveFnArity::NaryFold(2, 1, 0), Primitive::Sub),
+                            b"*" => (PrimitiveFnArity::NaryFold(2, 0, 1), Primitive::Mul),
+                            b"<" => (PrimitiveFnArity::NaryAllPairs(2), Primitive::Lt),
+                            b"=" => (PrimitiveFnArity::NaryAllPairs(2), Primitive::Eq),
+                            b"eq?" => (PrimitiveFnArity::NaryAllPairs(2), Primitive::EqP),
+                            b"zero?" => (PrimitiveFnArity::Unary, Primitive::ZeroP),
+                            b"integer?" => (PrimitiveFnArity::Unary, Primitive::IntegerP),
+                            b"boolean?" => (PrimitiveFnArity::Unary, Primitive::BooleanP),
+                            b"char?" => (PrimitiveFnArity::Unary, Primitive::CharP),
+                            b"null?" => (PrimitiveFnArity::Unary, Primitive::NullP),
+                            b"not" => (PrimitiveFnArity::Unary, Primitive::Not),
+                            b"char->integer" => (PrimitiveFnArity::Unary, Primitive::CharToInt),
+                            b"integer->char" => (PrimitiveFnArity::Unary, Primitive::IntToChar),
+                            _ => {
+                                return Err(CompileError {
+                                    span: head_span,
+                                    message: format!(
+                                        "Cannot resolve symbol '{}'",
+                                        interner.resolve_str(name)
+                                    ),
+                                });
+                            }
                         };
                         match arity {
                             PrimitiveFnArity::Unary => {
                                 if args.len() != 1 {
-                                    panic!("incorrect argument count for unary primitive function");
+                                    return Err(CompileError {
+                                        span: exp_span,
+                                        message: "incorrect argument count for unary primitive function"
+                                            .to_owned(),
+                                    });
                                 }
                                 for arg in args {
                                     result.append(&mut lower_expression(
                                         arg,
-                                        env.clone(),
+                                        Rc::clone(&env),
                                         stack_slots_used,
-                                    ));
+                                        functions,
+                                        interner,
+                                        backend,
+                                    )?);
                                 }
-                                result.push(mnemonic.to_owned())
+                                result.push(backend.primitive(prim))
                             }
                             PrimitiveFnArity::NaryAllPairs(implementation_arity) => {
                                 let mut stack_slots_used = stack_slots_used;
@@ -325,24 +1422,36 @@ fn lower_expression<'a>(
                                     for arg in args.into_iter() {
                                         result.append(&mut lower_expression(
                                             arg,
-                                            env.clone(),
+                                            Rc::clone(&env),
                                             stack_slots_used,
-                                        ));
-                                        result.push("FORGET".to_owned());
+                                            functions,
+                                            interner,
+                                            backend,
+                                        )?);
+                                        result.push(backend.forget());
                                     }
                                     result.append(&mut lower_expression(
-                                        Expression::Bool(true),
-                                        env.clone(),
+                                        Spanned {
+                                            value: Expression::Bool(true),
+                                            span: exp_span,
+                                        },
+                                        Rc::clone(&env),
                                         stack_slots_used,
-                                    ));
+                                        functions,
+                                        interner,
+                                        backend,
+                                    )?);
                                 } else {
                                     let num_args: usize = args.len();
                                     for arg in args {
                                         result.append(&mut lower_expression(
                                             arg,
-                                            env.clone(),
+                                            Rc::clone(&env),
                                             stack_slots_used,
-                                        ));
+                                            functions,
+                                            interner,
+                                            backend,
+                                        )?);
                                         stack_slots_used += 1;
                                     }
                                     // From this point forward, stack_slots_used is not updated, even though
@@ -350,16 +1459,16 @@ fn lower_expression<'a>(
                                     // in this match arm, so it would be a dead store.
                                     for (i, j) in (0..num_args).zip(1..num_args) {
                                         result.append(&mut vec![
-                                            "GET ".to_owned() + &i.to_string(),
-                                            "GET ".to_owned() + &j.to_string(),
-                                            "LT".to_owned(),
+                                            backend.get(i),
+                                            backend.get(j),
+                                            backend.primitive(Primitive::Lt),
                                         ]);
                                         if i != 0 {
-                                            result.push("AND".to_owned());
+                                            result.push(backend.and());
                                         }
                                     }
                                     for _ in 0..num_args {
-                                        result.push("FALL".to_owned());
+                                        result.push(backend.fall());
                                     }
                                 }
                             }
@@ -369,26 +1478,37 @@ fn lower_expression<'a>(
                                 default_argument,
                             ) => {
                                 if args.len() < min_args {
-                                    panic!(
-                                        "Too few arguments provided to NaryFold primitive function."
-                                    );
+                                    return Err(CompileError {
+                                        span: exp_span,
+                                        message: "Too few arguments provided to NaryFold primitive function."
+                                            .to_owned(),
+                                    });
                                 }
                                 while args.len() < implementation_arity {
-                                    args.insert(0, Expression::Int(default_argument));
+                                    args.insert(
+                                        0,
+                                        Spanned {
+                                            value: Expression::Int(default_argument),
+                                            span: exp_span,
+                                        },
+                                    );
                                 }
                                 let mut stack_slots_used = stack_slots_used;
                                 for (i, arg) in args.into_iter().enumerate() {
                                     result.append(&mut lower_expression(
                                         arg,
-                                        env.clone(),
+                                        Rc::clone(&env),
                                         stack_slots_used,
-                                    ));
+                                        functions,
+                                        interner,
+                                        backend,
+                                    )?);
                                     stack_slots_used += 1; // arg
                                     if (i == implementation_arity - 1)
                                         || (i >= implementation_arity
                                             && ((i % (implementation_arity - 1)) == 0))
                                     {
-                                        result.push(mnemonic.to_owned());
+                                        result.push(backend.primitive(prim));
                                         // Note: this cannot be rewritten as
                                         // `stack_slots_used -= 1 - implementation_arity`
                                         // because that will promote 1 to usize, and then underflow.
@@ -401,123 +1521,1597 @@ fn lower_expression<'a>(
                     }
                 }
             } else {
-                panic!("First entry in form is invalid.")
+                return Err(CompileError {
+                    span: head_span,
+                    message: "First entry in form is invalid.".to_owned(),
+                });
             }
         }
-        Expression::Null => result.push("LOAD64 NULL".to_owned()),
-        Expression::Symbol(name) => {
-            if let Some(env_index) = env.get(name) {
-                result.push("GET ".to_owned() + &env_index.to_string());
-            } else {
-                panic!(
-                    "Couldn't find environment entry for \"{}\"",
-                    from_utf8(name).unwrap()
-                )
-            }
+        Expression::Null => result.push(backend.load_null()),
+        Expression::Str(_) => {
+            return Err(CompileError {
+                span: exp_span,
+                message: "string literals are not supported by the compiler".to_owned(),
+            });
         }
+        Expression::Symbol(name) => match env.get(name) {
+            Some(EnvEntry::Local(env_index)) => result.push(backend.get(*env_index)),
+            Some(EnvEntry::Function { .. }) => {
+                return Err(CompileError {
+                    span: exp_span,
+                    message: format!(
+                        "'{}' is a function and must be called, not referenced",
+                        interner.resolve_str(name)
+                    ),
+                });
+            }
+            None => {
+                return Err(CompileError {
+                    span: exp_span,
+                    message: format!(
+                        "Couldn't find environment entry for \"{}\"",
+                        interner.resolve_str(name)
+                    ),
+                });
+            }
+        },
     };
-    result
+    Ok(result)
 }
 
-fn lower_expressions<'a>(
-    exps: Vec<Expression<'a>>,
-    env: HashMap<&'a [u8], usize>,
+fn lower_expressions(
+    exps: Vec<SExpr>,
+    env: Rc<Env>,
     stack_slots_used: usize,
-) -> Vec<String> {
+    functions: &mut Vec<String>,
+    interner: &Interner,
+    backend: &dyn Backend,
+) -> Result<Vec<String>, CompileError> {
     let mut result = Vec::new();
     let num_exps = exps.len();
     for (i, exp) in exps.into_iter().enumerate() {
-        result.append(&mut lower_expression(exp, env.clone(), stack_slots_used));
+        result.append(&mut lower_expression(
+            exp,
+            Rc::clone(&env),
+            stack_slots_used,
+            functions,
+            interner,
+            backend,
+        )?);
         if i != num_exps - 1 {
-            result.push("FORGET".to_owned())
+            result.push(backend.forget())
         }
     }
-    result
-}
-
-fn compile_all(input_slice: &[u8]) -> Vec<String> {
-    let (ast, input_slice) = consume_expressions(consume_whitespace(input_slice));
-    // dbg!(&ast);
-    if !input_slice.is_empty() {
-        panic!("Leftover data: {:?}", input_slice);
-    }
-    lower_expressions(ast, HashMap::new(), 0)
-}
-
-fn main() {
-    let mut input_vec = Vec::new();
-    let _bytes_read = stdin().read_to_end(&mut input_vec);
-    println!("{}", compile_all(&input_vec[..]).join("\n"))
-}
-
-#[test]
-#[should_panic(expected = "let bindings is not a form")]
-fn invalid_let_binding_list() {
-    compile_all(b"(let 1 1)");
+    Ok(result)
+}
+
+// Scans `exps` for symbols that resolve to an `EnvEntry::Local` in
+// `outer_env` but are not bound by `params` or by any binder (`let`/
+// `lambda`) nested inside `exps` itself. These are the free variables a
+// lambda body closes over by value. Names that resolve to an
+// `EnvEntry::Function` are deliberately excluded: those are reachable from
+// any scope via `function_bindings_only`, not by copying a value into the
+// closure's frame, so capturing them here would just shadow that binding
+// with a stale `Local` slot.
+fn collect_free_symbols(
+    exps: &[SExpr],
+    bound: &HashSet<SymbolId>,
+    outer_env: &Env,
+    let_id: SymbolId,
+    lambda_id: SymbolId,
+    found: &mut Vec<SymbolId>,
+) {
+    for exp in exps {
+        match &exp.value {
+            Expression::Symbol(name)
+                if !bound.contains(name)
+                    && matches!(outer_env.get(*name), Some(EnvEntry::Local(_)))
+                    && !found.contains(name) =>
+            {
+                found.push(*name);
+            }
+            Expression::Form(args) => match args.split_first() {
+                Some((head, rest))
+                    if matches!(&head.value, Expression::Symbol(name) if *name == let_id) =>
+                {
+                    if let Some(Spanned {
+                        value: Expression::Form(bindings),
+                        ..
+                    }) = rest.first()
+                    {
+                        // Binding values are evaluated in the enclosing scope
+                        // (not the scope being extended), so scan them against
+                        // the pre-`let` `bound` set.
+                        for binding in bindings {
+                            if let Expression::Form(binding) = &binding.value
+                                && let Some(value) = binding.get(1)
+                            {
+                                collect_free_symbols(
+                                    std::slice::from_ref(value),
+                                    bound,
+                                    outer_env,
+                                    let_id,
+                                    lambda_id,
+                                    found,
+                                );
+                            }
+                        }
+                        let mut bound = bound.clone();
+                        for binding in bindings {
+                            if let Expression::Form(binding) = &binding.value
+                                && let Some(Spanned {
+                                    value: Expression::Symbol(name),
+                                    ..
+                                }) = binding.first()
+                            {
+                                bound.insert(*name);
+                            }
+                        }
+                        collect_free_symbols(&rest[1..], &bound, outer_env, let_id, lambda_id, found);
+                    }
+                }
+                Some((head, rest))
+                    if matches!(&head.value, Expression::Symbol(name) if *name == lambda_id) =>
+                {
+                    if let Some(Spanned {
+                        value: Expression::Form(params),
+                        ..
+                    }) = rest.first()
+                    {
+                        let mut bound = bound.clone();
+                        for param in params {
+                            if let Expression::Symbol(name) = param.value {
+                                bound.insert(name);
+                            }
+                        }
+                        collect_free_symbols(&rest[1..], &bound, outer_env, let_id, lambda_id, found);
+                    }
+                }
+                _ => {
+                    // Calling another top-level function only works if every
+                    // one of *its* captures is also a local in the calling
+                    // function's own frame (captures are re-fetched by name
+                    // from the caller's env at each call site, not carried
+                    // inside a closure object). So a call to an
+                    // `EnvEntry::Function` pulls that callee's captures into
+                    // `found` too, transitively -- the callee was compiled
+                    // earlier, so its `captures` list is already complete.
+                    if let Some((head, _)) = args.split_first()
+                        && let Expression::Symbol(name) = &head.value
+                        && let Some(EnvEntry::Function { captures, .. }) = outer_env.get(*name)
+                    {
+                        for capture in captures {
+                            if !bound.contains(capture)
+                                && matches!(outer_env.get(*capture), Some(EnvEntry::Local(_)))
+                                && !found.contains(capture)
+                            {
+                                found.push(*capture);
+                            }
+                        }
+                    }
+                    collect_free_symbols(args, bound, outer_env, let_id, lambda_id, found)
+                }
+            },
+            _ => {}
+        }
+    }
+}
+
+// Rebuilds `env`, keeping only `EnvEntry::Function` bindings and dropping
+// every `EnvEntry::Local`. Function bindings are reachable from any nested
+// scope (a call site looks up its own captures separately), so this is what
+// lets a lambda body call sibling and enclosing top-level functions -- and,
+// combined with binding the function's own name in `compile_function`,
+// call itself -- without `Local` slots from an unrelated frame leaking in.
+fn function_bindings_only(env: &Env) -> Rc<Env> {
+    match env {
+        Env::Empty => Env::empty(),
+        Env::Extend(name, entry @ EnvEntry::Function { .. }, parent) => {
+            function_bindings_only(parent).extend(*name, entry.clone())
+        }
+        Env::Extend(_, EnvEntry::Local(_), parent) => function_bindings_only(parent),
+    }
+}
+
+// Lowers `(lambda (params...) body...)` into its own `LABEL`/`RET` block in
+// `functions`, and returns the `EnvEntry` callers should bind the function's
+// name to. Free variables in `body` are captured by value at each call site
+// (see the `EnvEntry::Function` call-lowering arm) rather than by allocating
+// an actual closure object, since the stack machine has nowhere else to put
+// them.
+#[allow(clippy::too_many_arguments)]
+fn compile_function(
+    name: SymbolId,
+    label: String,
+    params: Vec<SymbolId>,
+    param_span: Span,
+    body: Vec<SExpr>,
+    outer_env: &Env,
+    functions: &mut Vec<String>,
+    let_id: SymbolId,
+    lambda_id: SymbolId,
+    interner: &Interner,
+    backend: &dyn Backend,
+) -> Result<EnvEntry, CompileError> {
+    let mut bound: HashSet<SymbolId> = HashSet::new();
+    for param in &params {
+        if !bound.insert(*param) {
+            return Err(CompileError {
+                span: param_span,
+                message: "Duplicate parameter name in lambda".to_owned(),
+            });
+        }
+    }
+    let mut captures = Vec::new();
+    collect_free_symbols(&body, &bound, outer_env, let_id, lambda_id, &mut captures);
+
+    let entry = EnvEntry::Function {
+        label: label.clone(),
+        arity: params.len(),
+        captures: captures.clone(),
+    };
+
+    // Bind the function's own name first (lowest precedence), so that a
+    // param or capture that happens to share its name correctly shadows the
+    // self-reference, same as ordinary lexical scoping would.
+    let mut new_env = function_bindings_only(outer_env).extend(name, entry);
+    let mut stack_slots_used = 0;
+    for param in &params {
+        new_env = new_env.extend(*param, EnvEntry::Local(stack_slots_used));
+        stack_slots_used += 1;
+    }
+    for capture in &captures {
+        new_env = new_env.extend(*capture, EnvEntry::Local(stack_slots_used));
+        stack_slots_used += 1;
+    }
+
+    let mut body_code = lower_expressions(
+        body,
+        new_env,
+        stack_slots_used,
+        &mut Vec::new(),
+        interner,
+        backend,
+    )?;
+    functions.push(backend.label(&label));
+    functions.append(&mut body_code);
+    functions.push(backend.ret());
+
+    Ok(EnvEntry::Function {
+        label,
+        arity: params.len(),
+        captures,
+    })
+}
+
+// Compiles a top-level `(define name expr)` (with `define` already removed
+// from `args`), extending `env` with the new binding. `preamble` accumulates
+// the code for top-level value defines, which -- since the whole program is
+// really one flat scope -- are lowered exactly like `let` bindings that are
+// never torn down.
+#[allow(clippy::too_many_arguments)]
+fn compile_top_level_define(
+    define_span: Span,
+    mut args: Vec<SExpr>,
+    env: &mut Rc<Env>,
+    functions: &mut Vec<String>,
+    preamble: &mut Vec<String>,
+    stack_slots_used: usize,
+    let_id: SymbolId,
+    lambda_id: SymbolId,
+    interner: &Interner,
+    backend: &dyn Backend,
+) -> Result<usize, CompileError> {
+    if args.len() != 2 {
+        return Err(CompileError {
+            span: define_span,
+            message: "define requires exactly a name and a value expression".to_owned(),
+        });
+    }
+    let name_exp = args.remove(0);
+    let name_span = name_exp.span;
+    let name = match name_exp.value {
+        Expression::Symbol(name) => name,
+        _ => {
+            return Err(CompileError {
+                span: name_span,
+                message: "define name must be a symbol".to_owned(),
+            });
+        }
+    };
+    if env.get(name).is_some() {
+        return Err(CompileError {
+            span: name_span,
+            message: format!(
+                "Duplicate top-level definition of '{}'",
+                interner.resolve_str(name)
+            ),
+        });
+    }
+    let value_exp = args.remove(0);
+    let value_span = value_exp.span;
+    match value_exp.value {
+        Expression::Form(mut lambda_form)
+            if matches!(
+                lambda_form.first().map(|e| &e.value),
+                Some(Expression::Symbol(n)) if interner.resolve(*n) == b"lambda"
+            ) =>
+        {
+            lambda_form.remove(0);
+            if lambda_form.len() < 2 {
+                return Err(CompileError {
+                    span: value_span,
+                    message: "lambda requires a parameter list and at least one body expression"
+                        .to_owned(),
+                });
+            }
+            let params_exp = lambda_form.remove(0);
+            let params_span = params_exp.span;
+            let params = match params_exp.value {
+                Expression::Form(params) => {
+                    let mut result = Vec::with_capacity(params.len());
+                    for param in params {
+                        match param.value {
+                            Expression::Symbol(name) => result.push(name),
+                            _ => {
+                                return Err(CompileError {
+                                    span: param.span,
+                                    message: "lambda parameters must be symbols".to_owned(),
+                                });
+                            }
+                        }
+                    }
+                    result
+                }
+                _ => {
+                    return Err(CompileError {
+                        span: params_span,
+                        message: "lambda parameter list must be a form".to_owned(),
+                    });
+                }
+            };
+            let label = interner.resolve_str(name).to_owned();
+            let entry = compile_function(
+                name,
+                label,
+                params,
+                params_span,
+                lambda_form,
+                env,
+                functions,
+                let_id,
+                lambda_id,
+                interner,
+                backend,
+            )?;
+            *env = env.extend(name, entry);
+            Ok(stack_slots_used)
+        }
+        _ => {
+            preamble.append(&mut lower_expression(
+                value_exp,
+                Rc::clone(env),
+                stack_slots_used,
+                functions,
+                interner,
+                backend,
+            )?);
+            *env = env.extend(name, EnvEntry::Local(stack_slots_used));
+            Ok(stack_slots_used + 1)
+        }
+    }
+}
+
+fn compile_all(input_slice: &[u8], backend: &dyn Backend) -> Result<Vec<String>, CompileError> {
+    let origin_len = input_slice.len();
+    let mut interner = Interner::new();
+    let (ast, remaining) =
+        consume_expressions(origin_len, consume_whitespace(input_slice), &mut interner);
+    if !remaining.is_empty() {
+        let start = origin_len - remaining.len();
+        return Err(CompileError {
+            span: Span {
+                start,
+                end: origin_len,
+            },
+            message: format!("Leftover data: {remaining:?}"),
+        });
+    }
+    let define_syntax_id = interner.intern(b"define-syntax");
+    let define_id = interner.intern(b"define");
+    let let_id = interner.intern(b"let");
+    let lambda_id = interner.intern(b"lambda");
+
+    let mut macros = HashMap::new();
+    let mut non_macro_ast = Vec::new();
+    for exp in ast {
+        if matches!(&exp.value, Expression::Form(form) if matches!(form.first().map(|e| &e.value), Some(Expression::Symbol(name)) if *name == define_syntax_id))
+        {
+            register_macro(exp, &mut macros, &interner)?;
+        } else {
+            non_macro_ast.push(exp);
+        }
+    }
+    let mut gensym_counter = 0;
+    let mut ast = Vec::with_capacity(non_macro_ast.len());
+    for exp in non_macro_ast {
+        ast.push(expand_macros(exp, &macros, &mut interner, &mut gensym_counter)?);
+    }
+
+    let mut env = Env::empty();
+    let mut functions = Vec::new();
+    let mut preamble = Vec::new();
+    let mut stack_slots_used = 0;
+    let mut body_exprs = Vec::new();
+    for exp in ast {
+        let exp_span = exp.span;
+        match exp.value {
+            Expression::Form(mut form)
+                if matches!(form.first().map(|e| &e.value), Some(Expression::Symbol(name)) if *name == define_id) =>
+            {
+                form.remove(0);
+                stack_slots_used = compile_top_level_define(
+                    exp_span,
+                    form,
+                    &mut env,
+                    &mut functions,
+                    &mut preamble,
+                    stack_slots_used,
+                    let_id,
+                    lambda_id,
+                    &interner,
+                    backend,
+                )?;
+            }
+            other => body_exprs.push(Spanned {
+                value: other,
+                span: exp_span,
+            }),
+        }
+    }
+
+    let mut result = Vec::new();
+    if !functions.is_empty() {
+        result.push(backend.jump(functions.len()));
+        result.append(&mut functions);
+    }
+    result.append(&mut preamble);
+    result.append(&mut lower_expressions(
+        body_exprs,
+        env,
+        stack_slots_used,
+        &mut Vec::new(),
+        &interner,
+        backend,
+    )?);
+    Ok(backend.finalize(result))
+}
+
+// A runtime value produced by the tree-walking evaluator. Closures capture
+// their defining environment directly -- unlike `compile_function`, there's
+// no need to scan for free variables, since `EvalEnv` is already a cheap Rc
+// chain that can just be held onto.
+#[derive(Clone)]
+enum Value {
+    Int(u64),
+    Bool(bool),
+    Char(u8),
+    Null,
+    Closure {
+        params: Vec<SymbolId>,
+        body: Vec<SExpr>,
+        env: Rc<EvalEnv>,
+    },
+}
+
+impl std::fmt::Debug for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&format_value(self))
+    }
+}
+
+// A persistent environment mapping names directly to values, mirroring
+// `Env` but holding `Value`s instead of frame indices, since the evaluator
+// has no stack frames to index into. Each binding sits behind a `RefCell`
+// cell rather than a bare `Value`: `bind_self` needs to go back and fill in
+// a cell after the `Rc<EvalEnv>` that contains it has already been handed
+// to a closure, so that the closure can see its own (eventual) value
+// through the cell it captured.
+enum EvalEnv {
+    Empty,
+    Extend(SymbolId, Rc<RefCell<Value>>, Rc<EvalEnv>),
+}
+
+impl EvalEnv {
+    fn empty() -> Rc<EvalEnv> {
+        Rc::new(EvalEnv::Empty)
+    }
+
+    fn get(&self, name: SymbolId) -> Option<Value> {
+        match self {
+            EvalEnv::Empty => None,
+            EvalEnv::Extend(bound_name, value, parent) => {
+                if *bound_name == name {
+                    Some(value.borrow().clone())
+                } else {
+                    parent.get(name)
+                }
+            }
+        }
+    }
+
+    fn extend(self: &Rc<Self>, name: SymbolId, value: Value) -> Rc<EvalEnv> {
+        Rc::new(EvalEnv::Extend(
+            name,
+            Rc::new(RefCell::new(value)),
+            Rc::clone(self),
+        ))
+    }
+
+    // Extends `self` with `name` bound to a placeholder, then evaluates
+    // `make_value` against the *extended* env and backfills the cell with
+    // the result. A closure built by `make_value` that captures this env
+    // -- directly, for `(define name (lambda ...))`, or transitively
+    // through another binding -- sees `name` resolve to its own final
+    // value once the cell is filled in, which is what makes direct recursion
+    // through `define` possible. A closure does not see top-level names
+    // defined after it, so mutual recursion between two separate `define`s
+    // does not resolve.
+    fn bind_self(
+        self: &Rc<Self>,
+        name: SymbolId,
+        make_value: impl FnOnce(Rc<EvalEnv>) -> Result<Value, CompileError>,
+    ) -> Result<Rc<EvalEnv>, CompileError> {
+        let cell = Rc::new(RefCell::new(Value::Null));
+        let extended = Rc::new(EvalEnv::Extend(name, Rc::clone(&cell), Rc::clone(self)));
+        let value = make_value(Rc::clone(&extended))?;
+        *cell.borrow_mut() = value;
+        Ok(extended)
+    }
+}
+
+fn values_equal(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Int(x), Value::Int(y)) => x == y,
+        (Value::Bool(x), Value::Bool(y)) => x == y,
+        (Value::Char(x), Value::Char(y)) => x == y,
+        (Value::Null, Value::Null) => true,
+        _ => false,
+    }
+}
+
+fn format_value(value: &Value) -> String {
+    match value {
+        Value::Int(x) => x.to_string(),
+        Value::Bool(true) => "#t".to_owned(),
+        Value::Bool(false) => "#f".to_owned(),
+        Value::Char(x) => format!("#\\{}", *x as char),
+        Value::Null => "()".to_owned(),
+        Value::Closure { .. } => "#<closure>".to_owned(),
+    }
+}
+
+fn eval_expression(
+    exp: SExpr,
+    env: Rc<EvalEnv>,
+    interner: &Interner,
+) -> Result<Value, CompileError> {
+    let exp_span = exp.span;
+    match exp.value {
+        Expression::Int(x) => Ok(Value::Int(x)),
+        Expression::Bool(x) => Ok(Value::Bool(x)),
+        Expression::Char(x) => Ok(Value::Char(x)),
+        Expression::Null => Ok(Value::Null),
+        Expression::Str(_) => Err(CompileError {
+            span: exp_span,
+            message: "string literals are not supported by the evaluator".to_owned(),
+        }),
+        Expression::Symbol(name) => match env.get(name) {
+            Some(value) => Ok(value),
+            None => Err(CompileError {
+                span: exp_span,
+                message: format!(
+                    "Couldn't find environment entry for \"{}\"",
+                    interner.resolve_str(name)
+                ),
+            }),
+        },
+        Expression::Form(mut args) => {
+            if args.is_empty() {
+                return Err(CompileError {
+                    span: exp_span,
+                    message: "Empty form!".to_owned(),
+                });
+            }
+            let head = args.remove(0);
+            let head_span = head.span;
+            let Expression::Symbol(name) = head.value else {
+                return Err(CompileError {
+                    span: head_span,
+                    message: "First entry in form is invalid.".to_owned(),
+                });
+            };
+            if let Some(callee) = env.get(name) {
+                return eval_call(name, callee, args, exp_span, env, interner);
+            }
+            match interner.resolve(name) {
+                b"let" => eval_let(args, env, interner, exp_span),
+                b"if" => eval_if(args, env, interner, exp_span),
+                b"lambda" => eval_lambda(args, env, exp_span),
+                resolved_name => {
+                    eval_primitive(name, resolved_name, args, env, interner, exp_span, head_span)
+                }
+            }
+        }
+    }
+}
+
+fn eval_call(
+    name: SymbolId,
+    callee: Value,
+    args: Vec<SExpr>,
+    exp_span: Span,
+    env: Rc<EvalEnv>,
+    interner: &Interner,
+) -> Result<Value, CompileError> {
+    match callee {
+        Value::Closure {
+            params,
+            body,
+            env: closure_env,
+        } => {
+            if args.len() != params.len() {
+                return Err(CompileError {
+                    span: exp_span,
+                    message: format!(
+                        "Incorrect argument count calling '{}'",
+                        interner.resolve_str(name)
+                    ),
+                });
+            }
+            let mut call_env = Rc::clone(&closure_env);
+            for (param, arg) in params.iter().zip(args) {
+                let arg_value = eval_expression(arg, Rc::clone(&env), interner)?;
+                call_env = call_env.extend(*param, arg_value);
+            }
+            eval_body(body, call_env, interner)
+        }
+        _ => Err(CompileError {
+            span: exp_span,
+            message: format!(
+                "Cannot call '{}' because it is not a function",
+                interner.resolve_str(name)
+            ),
+        }),
+    }
+}
+
+fn eval_body(body: Vec<SExpr>, env: Rc<EvalEnv>, interner: &Interner) -> Result<Value, CompileError> {
+    let mut result = Value::Null;
+    for exp in body {
+        result = eval_expression(exp, Rc::clone(&env), interner)?;
+    }
+    Ok(result)
+}
+
+fn eval_let(
+    mut args: Vec<SExpr>,
+    env: Rc<EvalEnv>,
+    interner: &Interner,
+    exp_span: Span,
+) -> Result<Value, CompileError> {
+    if args.is_empty() {
+        return Err(CompileError {
+            span: exp_span,
+            message: "let bindings is not a form".to_owned(),
+        });
+    }
+    let bindings_exp = args.remove(0);
+    let bindings_span = bindings_exp.span;
+    let Expression::Form(bindings) = bindings_exp.value else {
+        return Err(CompileError {
+            span: bindings_span,
+            message: "let bindings is not a form".to_owned(),
+        });
+    };
+
+    let mut new_env = Rc::clone(&env);
+    for binding in bindings {
+        let binding_span = binding.span;
+        let Expression::Form(mut binding) = binding.value else {
+            return Err(CompileError {
+                span: binding_span,
+                message: "let binding is not a form".to_owned(),
+            });
+        };
+        if binding.len() != 2 {
+            return Err(CompileError {
+                span: binding_span,
+                message: "let binding has incorrect argument count.".to_owned(),
+            });
+        }
+        let (name_exp, value_exp) = (binding.remove(0), binding.remove(0));
+        let name_span = name_exp.span;
+        let Expression::Symbol(name) = name_exp.value else {
+            return Err(CompileError {
+                span: name_span,
+                message: "let binding args are not (Symbol, Expr)".to_owned(),
+            });
+        };
+        if new_env.get(name).is_some() {
+            return Err(CompileError {
+                span: name_span,
+                message: "Duplicate key in let binding".to_owned(),
+            });
+        }
+        let value = eval_expression(value_exp, Rc::clone(&env), interner)?;
+        new_env = new_env.extend(name, value);
+    }
+
+    eval_body(args, new_env, interner)
+}
+
+fn eval_if(
+    mut args: Vec<SExpr>,
+    env: Rc<EvalEnv>,
+    interner: &Interner,
+    exp_span: Span,
+) -> Result<Value, CompileError> {
+    if !matches!(args.len(), 2 | 3) {
+        return Err(CompileError {
+            span: exp_span,
+            message: "Invalid argument count to if".to_owned(),
+        });
+    }
+    let cond = eval_expression(args.remove(0), Rc::clone(&env), interner)?;
+    let consequent = args.remove(0);
+    if !matches!(cond, Value::Bool(false)) {
+        eval_expression(consequent, env, interner)
+    } else if let Some(alternative) = args.pop() {
+        eval_expression(alternative, env, interner)
+    } else {
+        Ok(Value::Null)
+    }
+}
+
+fn eval_lambda(
+    mut args: Vec<SExpr>,
+    env: Rc<EvalEnv>,
+    exp_span: Span,
+) -> Result<Value, CompileError> {
+    if args.len() < 2 {
+        return Err(CompileError {
+            span: exp_span,
+            message: "lambda requires a parameter list and at least one body expression"
+                .to_owned(),
+        });
+    }
+    let params_exp = args.remove(0);
+    let params_span = params_exp.span;
+    let Expression::Form(params) = params_exp.value else {
+        return Err(CompileError {
+            span: params_span,
+            message: "lambda parameter list must be a form".to_owned(),
+        });
+    };
+    let mut param_ids = Vec::with_capacity(params.len());
+    for param in params {
+        match param.value {
+            Expression::Symbol(name) => param_ids.push(name),
+            _ => {
+                return Err(CompileError {
+                    span: param.span,
+                    message: "lambda parameters must be symbols".to_owned(),
+                });
+            }
+        }
+    }
+    Ok(Value::Closure {
+        params: param_ids,
+        body: args,
+        env,
+    })
+}
+
+fn eval_primitive(
+    name: SymbolId,
+    resolved_name: &[u8],
+    args: Vec<SExpr>,
+    env: Rc<EvalEnv>,
+    interner: &Interner,
+    exp_span: Span,
+    head_span: Span,
+) -> Result<Value, CompileError> {
+    let mut values = Vec::with_capacity(args.len());
+    for arg in args {
+        let arg_span = arg.span;
+        values.push((eval_expression(arg, Rc::clone(&env), interner)?, arg_span));
+    }
+
+    let int_at = |i: usize| -> Result<u64, CompileError> {
+        match values[i].0 {
+            Value::Int(x) => Ok(x),
+            _ => Err(CompileError {
+                span: values[i].1,
+                message: "Expected an integer argument".to_owned(),
+            }),
+        }
+    };
+
+    match resolved_name {
+        b"add1" | b"sub1" | b"zero?" | b"integer?" | b"boolean?" | b"char?" | b"null?" | b"not"
+        | b"char->integer" | b"integer->char"
+            if values.len() != 1 =>
+        {
+            Err(CompileError {
+                span: exp_span,
+                message: "incorrect argument count for unary primitive function".to_owned(),
+            })
+        }
+        b"add1" => Ok(Value::Int(int_at(0)?.wrapping_add(1))),
+        b"sub1" => Ok(Value::Int(int_at(0)?.wrapping_sub(1))),
+        b"zero?" => Ok(Value::Bool(int_at(0)? == 0)),
+        b"integer?" => Ok(Value::Bool(matches!(values[0].0, Value::Int(_)))),
+        b"boolean?" => Ok(Value::Bool(matches!(values[0].0, Value::Bool(_)))),
+        b"char?" => Ok(Value::Bool(matches!(values[0].0, Value::Char(_)))),
+        b"null?" => Ok(Value::Bool(matches!(values[0].0, Value::Null))),
+        b"not" => Ok(Value::Bool(matches!(values[0].0, Value::Bool(false)))),
+        b"char->integer" => match values[0].0 {
+            Value::Char(x) => Ok(Value::Int(x as u64)),
+            _ => Err(CompileError {
+                span: values[0].1,
+                message: "Expected a character argument".to_owned(),
+            }),
+        },
+        b"integer->char" => Ok(Value::Char(int_at(0)? as u8)),
+        b"+" => {
+            let mut total: u64 = 0;
+            for i in 0..values.len() {
+                total = total.wrapping_add(int_at(i)?);
+            }
+            Ok(Value::Int(total))
+        }
+        b"-" => {
+            if values.is_empty() {
+                return Err(CompileError {
+                    span: exp_span,
+                    message: "Too few arguments provided to NaryFold primitive function."
+                        .to_owned(),
+                });
+            }
+            let mut total = int_at(0)?;
+            for i in 1..values.len() {
+                total = total.wrapping_sub(int_at(i)?);
+            }
+            Ok(Value::Int(total))
+        }
+        b"*" => {
+            let mut total: u64 = 1;
+            for i in 0..values.len() {
+                total = total.wrapping_mul(int_at(i)?);
+            }
+            Ok(Value::Int(total))
+        }
+        b"<" => {
+            for i in 1..values.len() {
+                if int_at(i - 1)? >= int_at(i)? {
+                    return Ok(Value::Bool(false));
+                }
+            }
+            Ok(Value::Bool(true))
+        }
+        b"=" => {
+            for i in 1..values.len() {
+                if int_at(i - 1)? != int_at(i)? {
+                    return Ok(Value::Bool(false));
+                }
+            }
+            Ok(Value::Bool(true))
+        }
+        b"eq?" => {
+            for i in 1..values.len() {
+                if !values_equal(&values[i - 1].0, &values[i].0) {
+                    return Ok(Value::Bool(false));
+                }
+            }
+            Ok(Value::Bool(true))
+        }
+        _ => Err(CompileError {
+            span: head_span,
+            message: format!("Cannot resolve symbol '{}'", interner.resolve_str(name)),
+        }),
+    }
+}
+
+// Parses and evaluates every top-level form in `input` against a persistent
+// `env`, threading `macros`/`interner`/`gensym_counter` through so that
+// `define-syntax` and `define` accumulate across repeated calls -- this is
+// what lets the REPL's top-level definitions persist across separate lines
+// of input.
+fn eval_program(
+    input: &[u8],
+    env: &mut Rc<EvalEnv>,
+    macros: &mut HashMap<SymbolId, Macro>,
+    interner: &mut Interner,
+    gensym_counter: &mut usize,
+) -> Result<Vec<Value>, CompileError> {
+    let origin_len = input.len();
+    let (ast, remaining) = consume_expressions(origin_len, consume_whitespace(input), interner);
+    if !remaining.is_empty() {
+        let start = origin_len - remaining.len();
+        return Err(CompileError {
+            span: Span {
+                start,
+                end: origin_len,
+            },
+            message: format!("Leftover data: {remaining:?}"),
+        });
+    }
+    let define_syntax_id = interner.intern(b"define-syntax");
+    let define_id = interner.intern(b"define");
+
+    let mut results = Vec::new();
+    for exp in ast {
+        if let Some(value) =
+            eval_top_level(exp, env, macros, interner, gensym_counter, define_syntax_id, define_id)?
+        {
+            results.push(value);
+        }
+    }
+    Ok(results)
+}
+
+// Handles one top-level form: registers a macro, extends `env` with a
+// `define`, or evaluates it as an ordinary expression. Redefining a
+// top-level name is allowed here (unlike `compile_top_level_define`'s
+// duplicate-define check), since shadowing an earlier definition is
+// expected REPL behavior.
+fn eval_top_level(
+    exp: SExpr,
+    env: &mut Rc<EvalEnv>,
+    macros: &mut HashMap<SymbolId, Macro>,
+    interner: &mut Interner,
+    gensym_counter: &mut usize,
+    define_syntax_id: SymbolId,
+    define_id: SymbolId,
+) -> Result<Option<Value>, CompileError> {
+    if matches!(&exp.value, Expression::Form(form) if matches!(form.first().map(|e| &e.value), Some(Expression::Symbol(name)) if *name == define_syntax_id))
+    {
+        register_macro(exp, macros, interner)?;
+        return Ok(None);
+    }
+
+    let exp = expand_macros(exp, macros, interner, gensym_counter)?;
+    let exp_span = exp.span;
+    match exp.value {
+        Expression::Form(mut form)
+            if matches!(form.first().map(|e| &e.value), Some(Expression::Symbol(name)) if *name == define_id) =>
+        {
+            form.remove(0);
+            if form.len() != 2 {
+                return Err(CompileError {
+                    span: exp_span,
+                    message: "define requires exactly a name and a value expression".to_owned(),
+                });
+            }
+            let name_exp = form.remove(0);
+            let name_span = name_exp.span;
+            let Expression::Symbol(name) = name_exp.value else {
+                return Err(CompileError {
+                    span: name_span,
+                    message: "define name must be a symbol".to_owned(),
+                });
+            };
+            let value_exp = form.remove(0);
+            *env = env.bind_self(name, |env| eval_expression(value_exp, env, interner))?;
+            Ok(None)
+        }
+        other => {
+            let value = eval_expression(
+                Spanned {
+                    value: other,
+                    span: exp_span,
+                },
+                Rc::clone(env),
+                interner,
+            )?;
+            Ok(Some(value))
+        }
+    }
+}
+
+// A read/eval/print loop over a persistent top-level environment, mirroring
+// the REPLs shipped by scripting engines like Rhai's `run` mode: each line
+// of stdin is parsed and evaluated independently, with `define`s and macros
+// accumulating in `env`/`macros` across lines.
+fn run_repl() {
+    let mut interner: Interner = Interner::new();
+    let mut macros = HashMap::new();
+    let mut gensym_counter = 0;
+    let mut env = EvalEnv::empty();
+
+    print!("> ");
+    let _ = stdout().flush();
+    for line in stdin().lock().lines() {
+        let Ok(line) = line else { break };
+        let input = line.as_bytes();
+        match eval_program(input, &mut env, &mut macros, &mut interner, &mut gensym_counter) {
+            Ok(values) => {
+                for value in values {
+                    println!("{}", format_value(&value));
+                }
+            }
+            Err(err) => eprintln!("{}", render_error(input, &err)),
+        }
+        print!("> ");
+        let _ = stdout().flush();
+    }
+}
+
+// Renders a diagnostic for `err` in the style of source-mapped compiler
+// output: the offending line, with a caret underline beneath the span.
+fn render_error(source: &[u8], err: &CompileError) -> String {
+    let start = err.span.start.min(source.len());
+    let end = err.span.end.clamp(start, source.len());
+    let line_start = source[..start]
+        .iter()
+        .rposition(|&b| b == b'\n')
+        .map_or(0, |i| i + 1);
+    let line_end = source[start..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .map_or(source.len(), |i| start + i);
+    let line = String::from_utf8_lossy(&source[line_start..line_end]);
+    let caret_col = start - line_start;
+    let caret_len = (end - start).max(1).min((line_end - start).max(1));
+    format!(
+        "error: {}\n{}\n{}{}",
+        err.message,
+        line,
+        " ".repeat(caret_col),
+        "^".repeat(caret_len)
+    )
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("--repl") {
+        run_repl();
+        return;
+    }
+
+    let backend: Box<dyn Backend> = match args.get(1).map(String::as_str) {
+        Some("--backend=c") => Box::new(CBackend),
+        Some("--backend=asm") | None => Box::new(AsmBackend),
+        Some(other) => {
+            eprintln!("error: unknown backend '{other}' (expected --backend=asm or --backend=c)");
+            std::process::exit(1);
+        }
+    };
+
+    let mut input_vec = Vec::new();
+    let _bytes_read = stdin().read_to_end(&mut input_vec);
+    match compile_all(&input_vec, backend.as_ref()) {
+        Ok(code) => println!("{}", code.join("\n")),
+        Err(err) => {
+            eprintln!("{}", render_error(&input_vec, &err));
+            std::process::exit(1);
+        }
+    }
+}
+
+#[test]
+fn invalid_let_binding_list() {
+    let err = compile_all(b"(let 1 1)", &AsmBackend).unwrap_err();
+    assert_eq!(err.message, "let bindings is not a form");
 }
 
 #[test]
-#[should_panic(expected = "let binding is not a form")]
 fn invalid_let_binding_list_entry() {
-    compile_all(b"(let (1) 1)");
+    let err = compile_all(b"(let (1) 1)", &AsmBackend).unwrap_err();
+    assert_eq!(err.message, "let binding is not a form");
 }
 
 #[test]
-#[should_panic(expected = "let binding has incorrect argument count.")]
 fn let_binding_too_many_args() {
-    compile_all(b"(let ((x 1 1)) x)");
+    let err = compile_all(b"(let ((x 1 1)) x)", &AsmBackend).unwrap_err();
+    assert_eq!(err.message, "let binding has incorrect argument count.");
 }
 
 #[test]
-#[should_panic(expected = "Duplicate key in let binding")]
 fn let_binding_duplicate_key() {
-    compile_all(b"(let ((x 1) (x 1)) x)");
+    let err = compile_all(b"(let ((x 1) (x 1)) x)", &AsmBackend).unwrap_err();
+    assert_eq!(err.message, "Duplicate key in let binding");
 }
 
 #[test]
-#[should_panic(expected = "let binding is not a form")]
 fn let_binding_list_not_nested() {
-    compile_all(b"(let (x 1) x)");
+    let err = compile_all(b"(let (x 1) x)", &AsmBackend).unwrap_err();
+    assert_eq!(err.message, "let binding is not a form");
 }
 
 #[test]
-#[should_panic(expected = "Invalid argument count to if")]
 fn too_few_if_args() {
-    compile_all(b"(if)");
+    let err = compile_all(b"(if)", &AsmBackend).unwrap_err();
+    assert_eq!(err.message, "Invalid argument count to if");
 }
 
 #[test]
-#[should_panic(expected = "Invalid argument count to if")]
 fn too_many_if_args() {
-    compile_all(b"(if 1 2 3 4)");
+    let err = compile_all(b"(if 1 2 3 4)", &AsmBackend).unwrap_err();
+    assert_eq!(err.message, "Invalid argument count to if");
 }
 
 #[test]
-#[should_panic(expected = "Leftover data: [93]")]
 fn leftover_data() {
-    compile_all(b"]");
+    let err = compile_all(b"]", &AsmBackend).unwrap_err();
+    assert_eq!(err.message, "Leftover data: [93]");
+    assert_eq!(err.span.start, 0);
 }
 
 #[test]
-#[should_panic(expected = "incorrect argument count for unary primitive function")]
 fn too_few_unary_args() {
-    compile_all(b"(not)");
+    let err = compile_all(b"(not)", &AsmBackend).unwrap_err();
+    assert_eq!(err.message, "incorrect argument count for unary primitive function");
 }
 
 #[test]
-#[should_panic(expected = "incorrect argument count for unary primitive function")]
 fn too_many_unary_args() {
-    compile_all(b"(not 1 2)");
+    let err = compile_all(b"(not 1 2)", &AsmBackend).unwrap_err();
+    assert_eq!(err.message, "incorrect argument count for unary primitive function");
 }
 
 #[test]
-#[should_panic(expected = "Too few arguments provided to NaryFold primitive function")]
 fn too_few_nary_args() {
-    compile_all(b"(-)");
+    let err = compile_all(b"(-)", &AsmBackend).unwrap_err();
+    assert_eq!(
+        err.message,
+        "Too few arguments provided to NaryFold primitive function."
+    );
 }
 
 #[test]
-#[should_panic(expected = "Couldn't find environment entry for \"a\"")]
 fn use_undefined_variable() {
-    compile_all(b"a");
+    let err = compile_all(b"a", &AsmBackend).unwrap_err();
+    assert_eq!(err.message, "Couldn't find environment entry for \"a\"");
+    assert_eq!(err.span, Span { start: 0, end: 1 });
+}
+
+#[test]
+fn define_and_call_function() {
+    let code = compile_all(b"(define f (lambda (x y) (+ x y))) (f 1 2)", &AsmBackend).unwrap();
+    assert!(code.contains(&"LABEL f".to_owned()));
+    assert!(code.contains(&"CALL f".to_owned()));
+    assert!(code.contains(&"RET".to_owned()));
+}
+
+#[test]
+fn function_closes_over_earlier_top_level_define() {
+    let code = compile_all(b"(define n 10) (define g (lambda (x) (+ x n))) (g 5)", &AsmBackend).unwrap();
+    assert!(code.contains(&"CALL g".to_owned()));
+}
+
+#[test]
+fn lambda_outside_define() {
+    let err = compile_all(b"(lambda (x) x)", &AsmBackend).unwrap_err();
+    assert_eq!(
+        err.message,
+        "lambda is only supported as the value of a top-level define"
+    );
+}
+
+#[test]
+fn call_with_wrong_argument_count() {
+    let err = compile_all(b"(define f (lambda (x) x)) (f 1 2)", &AsmBackend).unwrap_err();
+    assert_eq!(err.message, "Incorrect argument count calling 'f'");
+}
+
+#[test]
+fn duplicate_top_level_define() {
+    let err = compile_all(b"(define f 1) (define f 2) f", &AsmBackend).unwrap_err();
+    assert_eq!(err.message, "Duplicate top-level definition of 'f'");
+}
+
+#[test]
+fn call_non_function_value() {
+    let err = compile_all(b"(let ((x 1)) (x 2))", &AsmBackend).unwrap_err();
+    assert_eq!(err.message, "Cannot call 'x' because it is not a function");
+}
+
+#[test]
+fn error_points_at_offending_line() {
+    let err = compile_all(b"(let ((x 1))\n  (y 2))", &AsmBackend).unwrap_err();
+    let rendered = render_error(b"(let ((x 1))\n  (y 2))", &err);
+    assert!(rendered.contains("  (y 2))"));
+    assert!(rendered.contains('^'));
+}
+
+#[test]
+fn simple_macro_expands_at_use_site() {
+    let code = compile_all(
+        b"(define-syntax twice (syntax-rules () ((_ x) (+ x x)))) (twice 5)",
+        &AsmBackend,
+    )
+    .unwrap();
+    assert!(code.contains(&"ADD".to_owned()));
+}
+
+#[test]
+fn ellipsis_macro_expands_recursively() {
+    let code = compile_all(
+        b"(define-syntax my-or\n\
+            (syntax-rules ()\n\
+              ((_ ) #f)\n\
+              ((_ e) e)\n\
+              ((_ e1 e2 ...) (let ((t e1)) (if t t (my-or e2 ...))))))\n\
+          (my-or #f #f 3)",
+        &AsmBackend,
+    )
+    .unwrap();
+    assert!(code.iter().filter(|line| *line == "CJUMP 2").count() == 2);
+}
+
+#[test]
+fn macro_hygiene_renames_template_binder() {
+    // The use site passes its own variable named `t`; the macro's internal
+    // `t` binding must not capture it.
+    let code = compile_all(
+        b"(define-syntax my-or\n\
+            (syntax-rules ()\n\
+              ((_ e) e)\n\
+              ((_ e1 e2 ...) (let ((t e1)) (if t t (my-or e2 ...))))))\n\
+          (let ((t 1)) (my-or #f t))",
+        &AsmBackend,
+    )
+    .unwrap();
+    assert!(code.contains(&"LOAD64 #f".to_owned()));
+}
+
+#[test]
+fn macro_use_with_no_matching_rule() {
+    let err = compile_all(
+        b"(define-syntax one-arg (syntax-rules () ((_ x) x))) (one-arg 1 2)",
+        &AsmBackend,
+    )
+    .unwrap_err();
+    assert_eq!(
+        err.message,
+        "No matching syntax-rules pattern for macro 'one-arg'"
+    );
+}
+
+#[test]
+fn define_syntax_requires_syntax_rules() {
+    let err = compile_all(b"(define-syntax m (lambda (x) x)) (m 1)", &AsmBackend).unwrap_err();
+    assert_eq!(
+        err.message,
+        "define-syntax value must be a syntax-rules form"
+    );
+}
+
+#[cfg(test)]
+fn eval_source(input: &[u8]) -> Result<Vec<Value>, CompileError> {
+    let mut interner: Interner = Interner::new();
+    let mut macros = HashMap::new();
+    let mut gensym_counter = 0;
+    let mut env = EvalEnv::empty();
+    eval_program(input, &mut env, &mut macros, &mut interner, &mut gensym_counter)
+}
+
+#[cfg(test)]
+fn eval_one(input: &[u8]) -> Value {
+    eval_source(input).unwrap().pop().unwrap()
+}
+
+#[test]
+fn eval_arithmetic() {
+    assert!(matches!(eval_one(b"(+ 1 2 3)"), Value::Int(6)));
+}
+
+#[test]
+fn eval_let_and_if() {
+    assert!(matches!(
+        eval_one(b"(let ((x 5)) (if (< x 10) 1 0))"),
+        Value::Int(1)
+    ));
+}
+
+#[test]
+fn eval_lambda_closure_call() {
+    assert!(matches!(
+        eval_one(b"(let ((f (lambda (x y) (+ x y)))) (f 3 4))"),
+        Value::Int(7)
+    ));
+}
+
+#[test]
+fn eval_persists_top_level_define_across_calls() {
+    let mut interner: Interner = Interner::new();
+    let mut macros = HashMap::new();
+    let mut gensym_counter = 0;
+    let mut env = EvalEnv::empty();
+    eval_program(
+        b"(define n 10) (define f (lambda (x) (+ x n)))",
+        &mut env,
+        &mut macros,
+        &mut interner,
+        &mut gensym_counter,
+    )
+    .unwrap();
+    let values =
+        eval_program(b"(f 5)", &mut env, &mut macros, &mut interner, &mut gensym_counter).unwrap();
+    assert!(matches!(values.last(), Some(Value::Int(15))));
+}
+
+#[test]
+fn eval_predicate_primitives() {
+    assert!(matches!(eval_one(b"(zero? 0)"), Value::Bool(true)));
+    assert!(matches!(eval_one(b"(null? '())"), Value::Bool(true)));
+}
+
+#[test]
+fn eval_undefined_variable_errors() {
+    let err = eval_source(b"a").unwrap_err();
+    assert_eq!(err.message, "Couldn't find environment entry for \"a\"");
+}
+
+#[test]
+fn eval_call_non_function_errors() {
+    let err = eval_source(b"(let ((x 1)) (x 2))").unwrap_err();
+    assert_eq!(err.message, "Cannot call 'x' because it is not a function");
+}
+
+#[test]
+fn eval_macro_expands_before_eval() {
+    let values =
+        eval_source(b"(define-syntax twice (syntax-rules () ((_ x) (+ x x)))) (twice 21)")
+            .unwrap();
+    assert!(matches!(values.last(), Some(Value::Int(42))));
+}
+
+// Golden/snapshot harness: every program in this corpus must lower to the
+// same instruction shape regardless of which `Backend` is selected, even
+// though the mnemonics themselves differ. Since `Backend::primitive` match
+// arms are exhaustive over `Primitive`, a new primitive that's only wired
+// up for one backend fails to compile at all -- this test instead catches
+// divergence in the AST walk itself (e.g. a control-flow arm that emits an
+// extra instruction on one backend but not the other). `CBackend` resolves
+// its JUMP/CJUMP markers into extra `goto`-label lines that `AsmBackend`
+// has no equivalent of, so those are filtered out of the count rather than
+// disabling the comparison.
+#[cfg(test)]
+const BACKEND_COMPAT_CORPUS: &[&[u8]] = &[
+    b"(+ 1 2)",
+    b"(if (eq? 1 1) (add1 2) (sub1 2))",
+    b"(define f (lambda (x y) (+ x y))) (f 3 4)",
+    b"(let ((x 1) (y 2)) (< x y))",
+    b"(not (zero? 0))",
+    b"(integer? 5)",
+    b"(char->integer (integer->char 65))",
+];
+
+#[cfg(test)]
+fn is_c_goto_label(line: &str) -> bool {
+    line.starts_with("scrop_l") && line.ends_with(":;")
+}
+
+#[test]
+fn backends_agree_on_instruction_count_for_corpus() {
+    for program in BACKEND_COMPAT_CORPUS {
+        let asm_code = compile_all(program, &AsmBackend).unwrap();
+        let c_code = compile_all(program, &CBackend).unwrap();
+        let c_instruction_count = c_code.iter().filter(|line| !is_c_goto_label(line)).count();
+        assert_eq!(
+            asm_code.len(),
+            c_instruction_count,
+            "backends diverged on instruction count for {:?}",
+            from_utf8(program).unwrap()
+        );
+    }
+}
+
+#[test]
+fn c_backend_emits_scrop_style_mnemonics() {
+    let code = compile_all(b"(add1 1)", &CBackend).unwrap();
+    assert!(code.contains(&"scrop_add1();".to_owned()));
+}
+
+// A minimal stand-in for the runtime header a real `--backend=c` user would
+// supply: just enough of the PUSH/POP/CALL/RETURN/FALL/AND pseudo-ops and
+// scrop_* primitives to let `cc -fsyntax-only` check that CBackend's output
+// -- in particular, the `goto`s and labels `resolve_c_jumps` produces for
+// `if` -- is actually valid C, not merely instructions with a plausible
+// mnemonic.
+#[cfg(test)]
+const C_RUNTIME_STUB: &str = r#"
+typedef long scrop_value_t;
+static scrop_value_t stack[1024];
+static int sp = 0;
+#define PUSH(x) (stack[sp++] = (scrop_value_t)(x))
+#define POP() (stack[--sp])
+#define CALL(label) goto label
+#define RETURN() return
+#define FALL()
+#define AND()
+#define SCROP_NULL 0
+#define SCROP_UNSPECIFIED 0
+static void scrop_add1(void) { stack[sp - 1] += 1; }
+static void scrop_sub1(void) { stack[sp - 1] -= 1; }
+static void scrop_add(void) { stack[sp - 2] += stack[sp - 1]; sp--; }
+static void scrop_sub(void) { stack[sp - 2] -= stack[sp - 1]; sp--; }
+static void scrop_mul(void) { stack[sp - 2] *= stack[sp - 1]; sp--; }
+static void scrop_lt(void) { stack[sp - 2] = stack[sp - 2] < stack[sp - 1]; sp--; }
+static void scrop_eq(void) { stack[sp - 2] = stack[sp - 2] == stack[sp - 1]; sp--; }
+static void scrop_eqp(void) { stack[sp - 2] = stack[sp - 2] == stack[sp - 1]; sp--; }
+static void scrop_zerop(void) { stack[sp - 1] = stack[sp - 1] == 0; }
+static void scrop_integerp(void) { stack[sp - 1] = 1; }
+static void scrop_booleanp(void) { stack[sp - 1] = 1; }
+static void scrop_charp(void) { stack[sp - 1] = 1; }
+static void scrop_nullp(void) { stack[sp - 1] = 1; }
+static void scrop_not(void) { stack[sp - 1] = !stack[sp - 1]; }
+static void scrop_char_to_int(void) {}
+static void scrop_int_to_char(void) {}
+static void scrop_program(void) {
+"#;
+
+#[test]
+fn c_backend_output_is_syntactically_valid_c() {
+    let code = compile_all(
+        b"(define fact (lambda (n) (if (zero? n) 1 (* n (fact (sub1 n)))))) (fact 5)",
+        &CBackend,
+    )
+    .unwrap();
+    let source = format!(
+        "{C_RUNTIME_STUB}{}\n}}\nint main(void) {{ scrop_program(); return 0; }}\n",
+        code.join("\n")
+    );
+
+    let mut child = match std::process::Command::new("cc")
+        .args(["-fsyntax-only", "-xc", "-"])
+        .stdin(std::process::Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(_) => return, // no C compiler available in this environment; nothing to check
+    };
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(source.as_bytes())
+        .unwrap();
+    let status = child.wait().unwrap();
+    assert!(status.success(), "CBackend output is not valid C:\n{source}");
+}
+
+// Compiles `program` with `CBackend`, links it against `C_RUNTIME_STUB`, runs
+// the result, and returns the final top-of-stack value. Unlike
+// `c_backend_output_is_syntactically_valid_c`, this actually executes the
+// output, so a `resolve_c_jumps` bug that compiles fine but branches the
+// wrong way (as the inverted `CJUMP` polarity once did) fails a test instead
+// of only failing to compile. Returns `None` if no C compiler is available.
+// Only safe to call with call-free programs: `C_RUNTIME_STUB`'s `CALL`/
+// `RETURN` are bare `goto`/`return` with no real call frame, so a program
+// that actually calls a function can loop or read garbage instead of
+// returning.
+#[cfg(test)]
+fn run_c_backend(program: &[u8]) -> Option<i64> {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let code = compile_all(program, &CBackend).unwrap();
+    let source = format!(
+        "#include <stdio.h>\n{C_RUNTIME_STUB}{}\n}}\nint main(void) {{ scrop_program(); printf(\"%ld\", (long)stack[sp - 1]); return 0; }}\n",
+        code.join("\n")
+    );
+
+    let exe_path = std::env::temp_dir().join(format!(
+        "scrop_c_backend_test_{}_{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let mut child = match std::process::Command::new("cc")
+        .args(["-xc", "-", "-o"])
+        .arg(&exe_path)
+        .stdin(std::process::Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(_) => return None, // no C compiler available in this environment; nothing to check
+    };
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(source.as_bytes())
+        .unwrap();
+    let status = child.wait().unwrap();
+    assert!(status.success(), "CBackend output failed to compile:\n{source}");
+
+    let output = std::process::Command::new(&exe_path).output().unwrap();
+    let _ = std::fs::remove_file(&exe_path);
+    Some(
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .parse()
+            .unwrap(),
+    )
+}
+
+#[test]
+fn c_backend_if_true_takes_the_consequent() {
+    if let Some(result) = run_c_backend(b"(if #t 100 200)") {
+        assert_eq!(result, 100);
+    }
+}
+
+#[test]
+fn c_backend_if_false_takes_the_alternative() {
+    if let Some(result) = run_c_backend(b"(if #f 100 200)") {
+        assert_eq!(result, 200);
+    }
+}
+
+#[test]
+fn line_comment_is_skipped() {
+    let code = compile_all(b"(+ 1 2) ; trailing comment\n", &AsmBackend).unwrap();
+    assert!(code.contains(&"ADD".to_owned()));
+}
+
+#[test]
+fn nested_block_comment_is_skipped() {
+    let code = compile_all(b"#| outer #| inner |# still outer |# (+ 1 2)", &AsmBackend).unwrap();
+    assert!(code.contains(&"ADD".to_owned()));
+}
+
+#[test]
+fn unterminated_block_comment_consumes_rest_of_input() {
+    let code = compile_all(b"(+ 1 2) #| unterminated", &AsmBackend).unwrap();
+    assert!(code.contains(&"ADD".to_owned()));
+}
+
+#[test]
+fn quote_of_literal_emits_the_literal() {
+    let code = compile_all(b"(quote 42)", &AsmBackend).unwrap();
+    assert_eq!(code, vec!["LOAD64 42".to_owned()]);
+}
+
+#[test]
+fn quote_reader_macro_desugars_to_quote_form() {
+    let code = compile_all(b"'42", &AsmBackend).unwrap();
+    assert_eq!(code, vec!["LOAD64 42".to_owned()]);
+}
+
+#[test]
+fn quote_of_non_literal_is_rejected() {
+    let err = compile_all(b"(quote (1 2))", &AsmBackend).unwrap_err();
+    assert_eq!(
+        err.message,
+        "quote only supports literal data (integers, booleans, characters, and '())"
+    );
+}
+
+#[test]
+fn string_literal_parses_but_is_rejected_by_the_compiler() {
+    let err = compile_all(b"\"hello\"", &AsmBackend).unwrap_err();
+    assert_eq!(err.message, "string literals are not supported by the compiler");
+}
+
+#[test]
+fn string_literal_decodes_escapes() {
+    let (s, rest) = consume_string(b"\"a\\nb\\\"c\"").unwrap();
+    assert_eq!(s, b"a\nb\"c");
+    assert!(rest.is_empty());
+}
+
+#[test]
+fn quasiquote_and_unquote_markers_desugar() {
+    let (exp, rest) =
+        consume_expression(2, b",x", &mut Interner::new()).unwrap();
+    assert!(rest.is_empty());
+    assert!(matches!(exp.value, Expression::Form(form) if form.len() == 2));
+    let (exp, rest) =
+        consume_expression(2, b"`x", &mut Interner::new()).unwrap();
+    assert!(rest.is_empty());
+    assert!(matches!(exp.value, Expression::Form(form) if form.len() == 2));
 }